@@ -0,0 +1,482 @@
+use crate::model::{Detection, DetectionConfig, DetectionKind, PacketData, TransportProtocol, TCP_ACK, TCP_RST, TCP_SYN};
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+
+/// Numero massimo di host e porte elencati in una segnalazione: il conteggio
+/// resta comunque quello completo.
+const MAX_LISTED_TARGETS: usize = 100;
+/// Segnalazioni delle finestre chiuse conservate; oltre il limite si
+/// scartano le più vecchie.
+const MAX_CLOSED_DETECTIONS: usize = 1000;
+
+/// Obiettivi distinti sondati da una sorgente e finestra temporale dei tentativi.
+struct ProbeTargets<T> {
+    targets: BTreeSet<T>,
+    first_seen_us: u64,
+    last_seen_us: u64,
+}
+
+impl<T: Ord> ProbeTargets<T> {
+    fn new(timestamp_us: u64) -> Self {
+        ProbeTargets {
+            targets: BTreeSet::new(),
+            first_seen_us: timestamp_us,
+            last_seen_us: timestamp_us,
+        }
+    }
+
+    fn add(&mut self, target: T, timestamp_us: u64) {
+        self.targets.insert(target);
+        self.first_seen_us = self.first_seen_us.min(timestamp_us);
+        self.last_seen_us = self.last_seen_us.max(timestamp_us);
+    }
+
+    fn merge(&mut self, other: ProbeTargets<T>) {
+        self.targets.extend(other.targets);
+        self.first_seen_us = self.first_seen_us.min(other.first_seen_us);
+        self.last_seen_us = self.last_seen_us.max(other.last_seen_us);
+    }
+}
+
+/// Tentativo di connessione TCP: (sorgente, porta sorgente, destinazione, porta destinazione).
+type SynKey = (String, u16, String, u16);
+
+/// Esito di un tentativo: una porta aperta risponde con SYN-ACK, una chiusa con RST.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SynOutcome {
+    Unanswered,
+    Accepted,
+    Refused,
+}
+
+struct SynAttempt {
+    outcome: SynOutcome,
+    timestamp_us: u64,
+}
+
+/// Tentativi senza risposta di una sorgente.
+struct UnansweredProbes {
+    hosts: ProbeTargets<String>,
+    ports: BTreeSet<u16>,
+    count: usize,
+}
+
+impl UnansweredProbes {
+    fn merge(&mut self, other: UnansweredProbes) {
+        self.hosts.merge(other.hosts);
+        self.ports.extend(other.ports);
+        self.count += other.count;
+    }
+}
+
+/// Rileva attività di ricognizione a partire dai SYN TCP senza ACK, gli
+/// unici pacchetti che indicano con certezza chi ha aperto la connessione.
+/// Il traffico UDP non viene considerato. Le soglie valgono per finestre di
+/// `window_secs` sovrapposte per metà, così una scansione a cavallo di due
+/// finestre resta intera in una terza; la stessa attività segnalata da
+/// finestre vicine viene riunita in una sola segnalazione. Scansioni
+/// verticali e orizzontali contano solo i tentativi rifiutati o senza
+/// risposta, così un client che apre molte connessioni riuscite non viene
+/// segnalato.
+///
+/// I tentativi di connessione restano per file: `fold` li riduce agli
+/// insiemi per finestra e sorgente, gli unici uniti fra file diversi. Le
+/// finestre iniziate più di `window_secs` prima dell'ultima vengono chiuse
+/// e ne restano solo le segnalazioni.
+pub struct ScanDetector {
+    config: DetectionConfig,
+    window_us: u64,
+    step_us: u64,
+    syn_attempts: HashMap<SynKey, SynAttempt>,
+    latest_window_us: Option<u64>,
+    ports_by_host: HashMap<(u64, String, String), ProbeTargets<u16>>,
+    hosts_by_port: HashMap<(u64, String, u16), ProbeTargets<String>>,
+    unanswered: HashMap<(u64, String), UnansweredProbes>,
+    closed: Vec<Detection>,
+}
+
+fn merge_probes<K: Eq + Hash, T: Ord>(into: &mut HashMap<K, ProbeTargets<T>>, key: K, probes: ProbeTargets<T>) {
+    match into.get_mut(&key) {
+        Some(existing) => existing.merge(probes),
+        None => {
+            into.insert(key, probes);
+        }
+    }
+}
+
+fn vertical_scan(source: &str, host: &str, probes: &ProbeTargets<u16>) -> Detection {
+    Detection {
+        kind: DetectionKind::VerticalScan,
+        source: source.to_string(),
+        target_hosts: vec![host.to_string()],
+        target_ports: probes.targets.iter().take(MAX_LISTED_TARGETS).cloned().collect(),
+        count: probes.targets.len(),
+        first_seen_us: probes.first_seen_us,
+        last_seen_us: probes.last_seen_us,
+    }
+}
+
+fn horizontal_sweep(source: &str, port: u16, probes: &ProbeTargets<String>) -> Detection {
+    Detection {
+        kind: DetectionKind::HorizontalSweep,
+        source: source.to_string(),
+        target_hosts: probes.targets.iter().take(MAX_LISTED_TARGETS).cloned().collect(),
+        target_ports: vec![port],
+        count: probes.targets.len(),
+        first_seen_us: probes.first_seen_us,
+        last_seen_us: probes.last_seen_us,
+    }
+}
+
+/// Obiettivo che distingue due segnalazioni dello stesso tipo e sorgente.
+fn detection_target(detection: &Detection) -> (&[String], &[u16]) {
+    match detection.kind {
+        DetectionKind::VerticalScan => (&detection.target_hosts, &[]),
+        DetectionKind::HorizontalSweep => (&[], &detection.target_ports),
+        _ => (&[], &[]),
+    }
+}
+
+/// Riunisce le segnalazioni della stessa attività prodotte da finestre
+/// sovrapposte: stesso tipo, sorgente e obiettivo, intervalli che si toccano.
+fn coalesce(mut detections: Vec<Detection>) -> Vec<Detection> {
+    detections.sort_by(|a, b| {
+        a.kind.cmp(&b.kind)
+            .then_with(|| a.source.cmp(&b.source))
+            .then_with(|| detection_target(a).cmp(&detection_target(b)))
+            .then_with(|| a.first_seen_us.cmp(&b.first_seen_us))
+    });
+
+    let mut merged: Vec<Detection> = Vec::with_capacity(detections.len());
+    for detection in detections {
+        match merged.last_mut() {
+            Some(last) if last.kind == detection.kind
+                && last.source == detection.source
+                && detection_target(last) == detection_target(&detection)
+                && detection.first_seen_us <= last.last_seen_us =>
+            {
+                last.count = last.count.max(detection.count);
+                last.last_seen_us = last.last_seen_us.max(detection.last_seen_us);
+                let hosts: BTreeSet<String> = last.target_hosts.drain(..).chain(detection.target_hosts).collect();
+                last.target_hosts = hosts.into_iter().take(MAX_LISTED_TARGETS).collect();
+                let ports: BTreeSet<u16> = last.target_ports.drain(..).chain(detection.target_ports).collect();
+                last.target_ports = ports.into_iter().take(MAX_LISTED_TARGETS).collect();
+            }
+            _ => merged.push(detection),
+        }
+    }
+    merged
+}
+
+fn unanswered_syns(source: &str, probes: &UnansweredProbes) -> Detection {
+    Detection {
+        kind: DetectionKind::UnansweredSyn,
+        source: source.to_string(),
+        target_hosts: probes.hosts.targets.iter().take(MAX_LISTED_TARGETS).cloned().collect(),
+        target_ports: probes.ports.iter().take(MAX_LISTED_TARGETS).cloned().collect(),
+        count: probes.count,
+        first_seen_us: probes.hosts.first_seen_us,
+        last_seen_us: probes.hosts.last_seen_us,
+    }
+}
+
+impl ScanDetector {
+    pub fn new(config: &DetectionConfig) -> Self {
+        ScanDetector {
+            config: config.clone(),
+            window_us: config.window_secs.max(1) * 1_000_000,
+            step_us: config.window_secs.max(1) * 500_000,
+            syn_attempts: HashMap::new(),
+            latest_window_us: None,
+            ports_by_host: HashMap::new(),
+            hosts_by_port: HashMap::new(),
+            unanswered: HashMap::new(),
+            closed: Vec::new(),
+        }
+    }
+
+    pub fn add_packet(&mut self, packet: &PacketData) {
+        if packet.transport_layer != Some(TransportProtocol::Tcp) {
+            return;
+        }
+        let Some(flags) = packet.tcp_flags else { return };
+
+        if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 {
+            let key = (
+                packet.source_ip.clone(),
+                packet.source_port,
+                packet.destination_ip.clone(),
+                packet.destination_port,
+            );
+            let attempt = self.syn_attempts.entry(key).or_insert(SynAttempt {
+                outcome: SynOutcome::Unanswered,
+                timestamp_us: packet.timestamp_us,
+            });
+            attempt.timestamp_us = attempt.timestamp_us.min(packet.timestamp_us);
+        } else if flags & (TCP_ACK | TCP_RST) != 0 {
+            // Vale la prima risposta: un RST dopo il SYN-ACK chiude una connessione riuscita.
+            let key = (
+                packet.destination_ip.clone(),
+                packet.destination_port,
+                packet.source_ip.clone(),
+                packet.source_port,
+            );
+            if let Some(attempt) = self.syn_attempts.get_mut(&key)
+                && attempt.outcome == SynOutcome::Unanswered
+            {
+                attempt.outcome = if flags & TCP_RST != 0 { SynOutcome::Refused } else { SynOutcome::Accepted };
+            }
+        }
+    }
+
+    /// Riduce i tentativi di connessione agli insiemi di obiettivi per
+    /// finestra e sorgente. Ogni tentativo cade nelle due finestre che lo
+    /// contengono. Un tentativo che riceve risposta in un file successivo
+    /// resta contato come senza risposta.
+    pub fn fold(&mut self) {
+        let attempts = std::mem::take(&mut self.syn_attempts);
+        for ((source, _, destination, destination_port), attempt) in attempts {
+            let timestamp_us = attempt.timestamp_us;
+            let latest_start_us = timestamp_us - timestamp_us % self.step_us;
+            self.latest_window_us = self.latest_window_us.max(Some(latest_start_us));
+            if attempt.outcome == SynOutcome::Accepted {
+                continue;
+            }
+
+            let previous_start_us = latest_start_us.checked_sub(self.step_us);
+            for window_us in [Some(latest_start_us), previous_start_us].into_iter().flatten() {
+                self.add_probe(window_us, &source, &destination, destination_port, &attempt);
+            }
+        }
+        self.close_windows();
+    }
+
+    fn add_probe(&mut self, window_us: u64, source: &str, destination: &str, destination_port: u16, attempt: &SynAttempt) {
+        let timestamp_us = attempt.timestamp_us;
+        self.ports_by_host
+            .entry((window_us, source.to_string(), destination.to_string()))
+            .or_insert_with(|| ProbeTargets::new(timestamp_us))
+            .add(destination_port, timestamp_us);
+        self.hosts_by_port
+            .entry((window_us, source.to_string(), destination_port))
+            .or_insert_with(|| ProbeTargets::new(timestamp_us))
+            .add(destination.to_string(), timestamp_us);
+
+        if attempt.outcome == SynOutcome::Unanswered {
+            let probes = self.unanswered.entry((window_us, source.to_string())).or_insert_with(|| UnansweredProbes {
+                hosts: ProbeTargets::new(timestamp_us),
+                ports: BTreeSet::new(),
+                count: 0,
+            });
+            probes.hosts.add(destination.to_string(), timestamp_us);
+            probes.ports.insert(destination_port);
+            probes.count += 1;
+        }
+    }
+
+    pub fn merge(&mut self, mut other: ScanDetector) {
+        other.fold();
+        self.latest_window_us = self.latest_window_us.max(other.latest_window_us);
+        for (key, targets) in other.ports_by_host {
+            merge_probes(&mut self.ports_by_host, key, targets);
+        }
+        for (key, targets) in other.hosts_by_port {
+            merge_probes(&mut self.hosts_by_port, key, targets);
+        }
+        for (key, probes) in other.unanswered {
+            match self.unanswered.get_mut(&key) {
+                Some(existing) => existing.merge(probes),
+                None => {
+                    self.unanswered.insert(key, probes);
+                }
+            }
+        }
+        self.closed.extend(other.closed);
+        self.close_windows();
+    }
+
+    /// Valuta le finestre iniziate più di `window_secs` prima dell'ultima e
+    /// ne libera gli insiemi. Le più recenti restano aperte per i file che
+    /// arrivano in ritardo.
+    fn close_windows(&mut self) {
+        if let Some(latest_window_us) = self.latest_window_us {
+            let cutoff_us = latest_window_us.saturating_sub(self.window_us);
+            let config = &self.config;
+            for ((_, source, host), probes) in self.ports_by_host.extract_if(|(window_us, _, _), _| *window_us < cutoff_us) {
+                if probes.targets.len() >= config.vertical_scan_ports {
+                    self.closed.push(vertical_scan(&source, &host, &probes));
+                }
+            }
+            for ((_, source, port), probes) in self.hosts_by_port.extract_if(|(window_us, _, _), _| *window_us < cutoff_us) {
+                if probes.targets.len() >= config.horizontal_sweep_hosts {
+                    self.closed.push(horizontal_sweep(&source, port, &probes));
+                }
+            }
+            for ((_, source), probes) in self.unanswered.extract_if(|(window_us, _), _| *window_us < cutoff_us) {
+                if probes.count >= config.unanswered_syns {
+                    self.closed.push(unanswered_syns(&source, &probes));
+                }
+            }
+        }
+
+        if self.closed.len() > MAX_CLOSED_DETECTIONS {
+            self.closed = coalesce(std::mem::take(&mut self.closed));
+        }
+        if self.closed.len() > MAX_CLOSED_DETECTIONS {
+            self.closed.sort_by_key(|detection| std::cmp::Reverse(detection.last_seen_us));
+            self.closed.truncate(MAX_CLOSED_DETECTIONS);
+        }
+    }
+
+    /// Segnalazioni che superano le soglie in una finestra, riunite fra
+    /// finestre sovrapposte e ordinate per tipo, numero di obiettivi
+    /// decrescente e sorgente. Considera solo i tentativi già ridotti con `fold`.
+    pub fn detections(&self) -> Vec<Detection> {
+        let mut detections = self.closed.clone();
+
+        for ((_, source, host), probes) in self.ports_by_host.iter() {
+            if probes.targets.len() >= self.config.vertical_scan_ports {
+                detections.push(vertical_scan(source, host, probes));
+            }
+        }
+        for ((_, source, port), probes) in self.hosts_by_port.iter() {
+            if probes.targets.len() >= self.config.horizontal_sweep_hosts {
+                detections.push(horizontal_sweep(source, *port, probes));
+            }
+        }
+        for ((_, source), probes) in self.unanswered.iter() {
+            if probes.count >= self.config.unanswered_syns {
+                detections.push(unanswered_syns(source, probes));
+            }
+        }
+
+        let mut detections = coalesce(detections);
+        detections.sort_by(|a, b| {
+            a.kind.cmp(&b.kind)
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| a.source.cmp(&b.source))
+                .then_with(|| a.first_seen_us.cmp(&b.first_seen_us))
+                .then_with(|| a.target_hosts.cmp(&b.target_hosts))
+                .then_with(|| a.target_ports.cmp(&b.target_ports))
+        });
+        detections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const SYN: u8 = TCP_SYN;
+    const SYN_ACK: u8 = TCP_SYN | TCP_ACK;
+    const RST_ACK: u8 = TCP_RST | TCP_ACK;
+
+    fn tcp_packet(source: (&str, u16), destination: (&str, u16), flags: u8, timestamp_us: u64) -> PacketData {
+        PacketData::tcp_segment(source, destination, flags, 60, timestamp_us)
+    }
+
+    fn config() -> DetectionConfig {
+        DetectionConfig {
+            vertical_scan_ports: 10,
+            horizontal_sweep_hosts: 10,
+            unanswered_syns: 10,
+            window_secs: 60,
+        }
+    }
+
+    /// Un SYN da 10.0.0.1 verso `host`:`port`, con l'eventuale risposta.
+    fn probe(detector: &mut ScanDetector, host: &str, port: u16, reply: Option<u8>, timestamp_us: u64) {
+        let client_port = 40000 + port;
+        detector.add_packet(&tcp_packet(("10.0.0.1", client_port), (host, port), SYN, timestamp_us));
+        if let Some(flags) = reply {
+            detector.add_packet(&tcp_packet((host, port), ("10.0.0.1", client_port), flags, timestamp_us + 1000));
+        }
+    }
+
+    #[test]
+    fn accepted_connections_are_not_a_sweep() {
+        let mut detector = ScanDetector::new(&config());
+        for i in 0..50 {
+            probe(&mut detector, &format!("192.0.2.{}", i), 443, Some(SYN_ACK), i * 1000);
+        }
+        detector.fold();
+        assert!(detector.detections().is_empty());
+    }
+
+    #[test]
+    fn refused_probes_count_for_scans_but_not_as_unanswered() {
+        let mut detector = ScanDetector::new(&config());
+        for port in 1..=20 {
+            probe(&mut detector, "192.0.2.1", port, Some(RST_ACK), u64::from(port) * 1000);
+        }
+        detector.fold();
+        let detections = detector.detections();
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].kind, DetectionKind::VerticalScan);
+        assert_eq!(detections[0].count, 20);
+    }
+
+    #[test]
+    fn unanswered_probes_trigger_every_kind() {
+        let mut detector = ScanDetector::new(&config());
+        for i in 0..12 {
+            probe(&mut detector, &format!("192.0.2.{}", i), 22, None, u64::from(i) * 1000);
+            probe(&mut detector, "198.51.100.1", 100 + i, None, u64::from(i) * 1000);
+        }
+        detector.fold();
+        let kinds: Vec<DetectionKind> = detector.detections().into_iter().map(|d| d.kind).collect();
+        assert!(kinds.contains(&DetectionKind::VerticalScan));
+        assert!(kinds.contains(&DetectionKind::HorizontalSweep));
+        assert!(kinds.contains(&DetectionKind::UnansweredSyn));
+    }
+
+    #[test]
+    fn probes_spread_across_windows_are_not_flagged() {
+        let mut detector = ScanDetector::new(&config());
+        for port in 1..=20 {
+            // Una porta ogni 10 minuti: al più una per finestra di 60 secondi.
+            probe(&mut detector, "192.0.2.1", port, None, u64::from(port) * 600_000_000);
+        }
+        detector.fold();
+        assert!(detector.detections().is_empty());
+        assert!(detector.ports_by_host.len() <= 2);
+    }
+
+    #[test]
+    fn scans_in_overlapping_windows_are_reported_once() {
+        let mut detector = ScanDetector::new(&config());
+        // 16 porte fra 45 e 75 secondi: 8 per lato del confine dei 60 secondi.
+        for port in 0..16u16 {
+            probe(&mut detector, "192.0.2.1", 1000 + port, Some(RST_ACK), 45_000_000 + u64::from(port) * 2_000_000);
+        }
+        // 12 porte fra 35 e 46 secondi: intere sia in [0, 60) sia in [30, 90).
+        for port in 0..12u16 {
+            probe(&mut detector, "192.0.2.2", 2000 + port, Some(RST_ACK), 35_000_000 + u64::from(port) * 1_000_000);
+        }
+        detector.fold();
+
+        let detections = detector.detections();
+        assert_eq!(detections.len(), 2);
+        assert!(detections.iter().all(|d| d.kind == DetectionKind::VerticalScan));
+        assert_eq!((detections[0].target_hosts[0].as_str(), detections[0].count), ("192.0.2.1", 16));
+        assert_eq!(detections[0].first_seen_us, 45_000_000);
+        assert_eq!((detections[1].target_hosts[0].as_str(), detections[1].count), ("192.0.2.2", 12));
+    }
+
+    #[test]
+    fn closed_windows_keep_detections_across_merges() {
+        let mut first = ScanDetector::new(&config());
+        for port in 1..=15 {
+            probe(&mut first, "192.0.2.1", port, None, u64::from(port) * 1000);
+        }
+        let mut second = ScanDetector::new(&config());
+        probe(&mut second, "192.0.2.2", 80, None, 3_600_000_000);
+
+        let mut total = ScanDetector::new(&config());
+        total.merge(first);
+        total.merge(second);
+        assert!(total.ports_by_host.keys().all(|(window_us, _, _)| *window_us >= 3_540_000_000));
+        let detections = total.detections();
+        assert_eq!(detections.len(), 2);
+        assert!(detections.iter().all(|d| d.target_hosts == vec!["192.0.2.1".to_string()]));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::model::{DnsAnswer, DnsInfo, DnsQuestion};
+use crate::util::ByteReader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const RCODE_MASK: u16 = 0x000F;
+const LABEL_POINTER: u8 = 0xC0;
+const MAX_NAME_LEN: usize = 255;
+const MAX_POINTER_JUMPS: usize = 16;
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const MAX_CNAME_CHAIN: usize = 8;
+const MAX_NAMES_PER_ADDRESS: usize = 16;
+
+/// Decodifica l'header, la sezione question e, nelle risposte, i record
+/// A/AAAA/CNAME della sezione answer. Su TCP il messaggio è preceduto da due
+/// byte di lunghezza che vengono saltati.
+pub fn parse_dns_message(payload: &[u8], over_tcp: bool) -> Option<DnsInfo> {
+    let message = if over_tcp { payload.get(2..)? } else { payload };
+    let mut reader = ByteReader::new(message);
+
+    let _id = reader.read_u16()?;
+    let flags = reader.read_u16()?;
+    let question_count = reader.read_u16()?;
+    let answer_count = reader.read_u16()?;
+    reader.skip(HEADER_LEN - 8)?;
+
+    let mut offset = HEADER_LEN;
+    // QDCOUNT arriva dal pacchetto: non va usato per preallocare.
+    let mut questions = Vec::new();
+    for _ in 0..question_count {
+        let (name, name_end) = read_name(message, offset)?;
+        let mut fields = ByteReader::new(message.get(name_end..)?);
+        let query_type = fields.read_u16()?;
+        let _class = fields.read_u16()?;
+        offset = name_end + 4;
+        questions.push(DnsQuestion { name, query_type });
+    }
+
+    let is_response = flags & FLAG_RESPONSE != 0;
+    let answers = if is_response { parse_answers(message, offset, answer_count) } else { Vec::new() };
+
+    Some(DnsInfo {
+        is_response,
+        response_code: (flags & RCODE_MASK) as u8,
+        questions,
+        answers,
+    })
+}
+
+/// Legge la sezione answer fermandosi al primo record malformato o troncato:
+/// i record già letti restano validi.
+fn parse_answers(message: &[u8], mut offset: usize, answer_count: u16) -> Vec<DnsAnswer> {
+    let mut answers = Vec::new();
+
+    for _ in 0..answer_count {
+        let Some((name, record_type, data_start, data)) = read_record(message, offset) else { break };
+        offset = data_start + data.len();
+
+        match (record_type, data.len()) {
+            (TYPE_A, 4) => {
+                let address = IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+                answers.push(DnsAnswer::Address { name, address });
+            }
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                answers.push(DnsAnswer::Address { name, address: IpAddr::V6(Ipv6Addr::from(octets)) });
+            }
+            (TYPE_CNAME, _) => {
+                if let Some((target, _)) = read_name(message, data_start) {
+                    answers.push(DnsAnswer::Cname { name, target });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    answers
+}
+
+/// Legge un resource record: nome, tipo, offset e contenuto dei dati.
+fn read_record(message: &[u8], offset: usize) -> Option<(String, u16, usize, &[u8])> {
+    let (name, name_end) = read_name(message, offset)?;
+    let mut fields = ByteReader::new(message.get(name_end..)?);
+    let record_type = fields.read_u16()?;
+    fields.skip(6)?; // classe e TTL
+    let data_len = fields.read_u16()? as usize;
+    let data_start = name_end + 10;
+    let data = message.get(data_start..data_start + data_len)?;
+    Some((name, record_type, data_start, data))
+}
+
+/// Legge un nome a partire da `offset` seguendo i puntatori di compressione.
+/// Restituisce il nome e l'offset del primo byte dopo il nome nel messaggio.
+fn read_name(message: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut position = offset;
+    let mut end = None;
+    let mut jumps = 0;
+    let mut name_len = 0;
+
+    loop {
+        let len = *message.get(position)?;
+        if len & LABEL_POINTER == LABEL_POINTER {
+            let pointer = u16::from_be_bytes([len & !LABEL_POINTER, *message.get(position + 1)?]) as usize;
+            if end.is_none() {
+                end = Some(position + 2);
+            }
+            jumps += 1;
+            if jumps > MAX_POINTER_JUMPS {
+                return None;
+            }
+            position = pointer;
+        } else if len & LABEL_POINTER != 0 {
+            return None;
+        } else if len == 0 {
+            break;
+        } else {
+            let label = message.get(position + 1..position + 1 + len as usize)?;
+            name_len += len as usize + 1;
+            if name_len > MAX_NAME_LEN {
+                return None;
+            }
+            labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+            position += 1 + len as usize;
+        }
+    }
+
+    let name = if labels.is_empty() { ".".to_string() } else { labels.join(".") };
+    Some((name, end.unwrap_or(position + 1)))
+}
+
+pub fn query_type_name(query_type: u16) -> String {
+    let name = match query_type {
+        1 => "A",
+        2 => "NS",
+        5 => "CNAME",
+        6 => "SOA",
+        12 => "PTR",
+        15 => "MX",
+        16 => "TXT",
+        28 => "AAAA",
+        33 => "SRV",
+        35 => "NAPTR",
+        43 => "DS",
+        46 => "RRSIG",
+        48 => "DNSKEY",
+        64 => "SVCB",
+        65 => "HTTPS",
+        255 => "ANY",
+        _ => return format!("TYPE{}", query_type),
+    };
+    name.to_string()
+}
+
+pub fn response_code_name(response_code: u8) -> String {
+    let name = match response_code {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        _ => return format!("RCODE{}", response_code),
+    };
+    name.to_string()
+}
+
+/// Nomi risolti in un indirizzo, con il numero di risposte e il timestamp
+/// dell'ultima.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct ResolvedNames {
+    names: HashMap<String, u32>,
+    last_seen_us: u64,
+}
+
+/// Tabella passive DNS: per ogni indirizzo i nomi che vi sono stati risolti
+/// e quante volte. Il numero di nomi per indirizzo è limitato per non far
+/// crescere la tabella con gli indirizzi condivisi dalle CDN; quello degli
+/// indirizzi con `prune`, che scarta quelli risolti meno di recente.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(transparent)]
+pub struct PassiveDnsTable {
+    addresses: HashMap<String, ResolvedNames>,
+}
+
+/// Tabella condivisa fra aggregatore e worker: l'aggregatore vi unisce le
+/// risoluzioni di ogni file, i worker la consultano per etichettare gli
+/// indirizzi risolti in file precedenti.
+pub type SharedPassiveDns = Arc<RwLock<PassiveDnsTable>>;
+
+/// Carica la tabella salvata da un'esecuzione precedente. Un file assente o
+/// illeggibile non impedisce l'avvio: si riparte da una tabella vuota.
+pub fn load_passive_dns(path: &str) -> PassiveDnsTable {
+    if !Path::new(path).exists() {
+        return PassiveDnsTable::default();
+    }
+    match crate::util::read_json_file_as::<PassiveDnsTable, _>(path) {
+        Ok(table) => {
+            println!("Tabella passive DNS caricata: {} indirizzi", table.addresses.len());
+            table
+        }
+        Err(e) => {
+            eprintln!("Errore nel caricamento della tabella passive DNS {}: {}", path, e);
+            PassiveDnsTable::default()
+        }
+    }
+}
+
+impl PassiveDnsTable {
+    /// Registra le risoluzioni di una risposta. Seguendo la catena dei CNAME
+    /// all'indietro, l'indirizzo viene associato al nome richiesto dal client.
+    pub fn record(&mut self, dns: &DnsInfo, timestamp_us: u64) {
+        for answer in dns.answers.iter() {
+            if let DnsAnswer::Address { name, address } = answer {
+                let mut hostname = name;
+                for _ in 0..MAX_CNAME_CHAIN {
+                    let alias = dns.answers.iter().find_map(|answer| match answer {
+                        DnsAnswer::Cname { name, target } if target == hostname => Some(name),
+                        _ => None,
+                    });
+                    match alias {
+                        Some(alias) => hostname = alias,
+                        None => break,
+                    }
+                }
+                self.add(address.to_string(), hostname.clone(), 1, timestamp_us);
+            }
+        }
+    }
+
+    fn add(&mut self, address: String, hostname: String, count: u32, last_seen_us: u64) {
+        let resolved = self.addresses.entry(address).or_default();
+        resolved.last_seen_us = resolved.last_seen_us.max(last_seen_us);
+        let names = &mut resolved.names;
+        if names.len() < MAX_NAMES_PER_ADDRESS || names.contains_key(&hostname) {
+            *names.entry(hostname).or_insert(0) += count;
+        }
+    }
+
+    pub fn merge(&mut self, other: PassiveDnsTable) {
+        for (address, resolved) in other.addresses {
+            for (hostname, count) in resolved.names {
+                self.add(address.clone(), hostname, count, resolved.last_seen_us);
+            }
+        }
+    }
+
+    /// Conserva al massimo `limit` indirizzi, scartando quelli risolti meno
+    /// di recente.
+    pub fn prune(&mut self, limit: usize) {
+        if self.addresses.len() <= limit {
+            return;
+        }
+        let excess = self.addresses.len() - limit;
+        let mut by_age: Vec<(u64, &String)> = self.addresses
+            .iter()
+            .map(|(address, resolved)| (resolved.last_seen_us, address))
+            .collect();
+        by_age.select_nth_unstable(excess - 1);
+        let oldest: Vec<String> = by_age[..excess].iter().map(|(_, address)| (*address).clone()).collect();
+        for address in oldest {
+            self.addresses.remove(&address);
+        }
+    }
+
+    /// Il nome risolto più spesso per l'indirizzo; a parità vince il minore.
+    pub fn hostname(&self, address: &str) -> Option<String> {
+        self.addresses
+            .get(address)?
+            .names
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(hostname, _)| hostname.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY_HEADER: [u8; 12] = [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    fn question(name: &[u8], query_type: u16) -> Vec<u8> {
+        let mut bytes = name.to_vec();
+        bytes.extend(query_type.to_be_bytes());
+        bytes.extend([0x00, 0x01]);
+        bytes
+    }
+
+    /// Risposta per www.example.com: CNAME verso cdn.example.net e record A
+    /// del target, con nomi compressi tramite puntatori.
+    fn cname_response() -> Vec<u8> {
+        let mut message = vec![0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00];
+        message.extend(question(b"\x03www\x07example\x03com\x00", TYPE_A));
+        // CNAME: nome = puntatore alla question (offset 12), target cdn.example.net.
+        message.extend([0xC0, 0x0C, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x11]);
+        message.extend(b"\x03cdn\x07example\x03net\x00");
+        // A: nome = puntatore al target del CNAME (offset 45).
+        message.extend([0xC0, 0x2D, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x04, 192, 0, 2, 10]);
+        message
+    }
+
+    #[test]
+    fn parses_query_question() {
+        let mut message = QUERY_HEADER.to_vec();
+        message.extend(question(b"\x07Example\x03org\x00", 28));
+
+        let dns = parse_dns_message(&message, false).unwrap();
+        assert!(!dns.is_response);
+        assert_eq!(dns.questions.len(), 1);
+        assert_eq!(dns.questions[0].name, "example.org");
+        assert_eq!(dns.questions[0].query_type, 28);
+    }
+
+    #[test]
+    fn skips_length_prefix_over_tcp() {
+        let mut message = QUERY_HEADER.to_vec();
+        message.extend(question(b"\x07example\x03org\x00", TYPE_A));
+        let mut payload = (message.len() as u16).to_be_bytes().to_vec();
+        payload.extend(message);
+
+        let dns = parse_dns_message(&payload, true).unwrap();
+        assert_eq!(dns.questions[0].name, "example.org");
+    }
+
+    #[test]
+    fn follows_compression_pointers_in_answers() {
+        let dns = parse_dns_message(&cname_response(), false).unwrap();
+        assert!(dns.is_response);
+        assert_eq!(dns.answers.len(), 2);
+        assert!(matches!(&dns.answers[0],
+            DnsAnswer::Cname { name, target } if name == "www.example.com" && target == "cdn.example.net"));
+        assert!(matches!(&dns.answers[1],
+            DnsAnswer::Address { name, address } if name == "cdn.example.net" && address.to_string() == "192.0.2.10"));
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        let mut message = QUERY_HEADER.to_vec();
+        message.extend([0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01]);
+        assert!(read_name(&message, HEADER_LEN).is_none());
+        assert!(parse_dns_message(&message, false).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_questions_with_large_count() {
+        let mut message = QUERY_HEADER.to_vec();
+        message[4] = 0xFF;
+        message[5] = 0xFF;
+        message.extend(question(b"\x07example\x03org\x00", TYPE_A));
+        assert!(parse_dns_message(&message, false).is_none());
+    }
+
+    #[test]
+    fn passive_dns_labels_address_with_queried_name() {
+        let dns = parse_dns_message(&cname_response(), false).unwrap();
+        let mut table = PassiveDnsTable::default();
+        table.record(&dns, 0);
+        assert_eq!(table.hostname("192.0.2.10").as_deref(), Some("www.example.com"));
+        assert_eq!(table.hostname("192.0.2.11"), None);
+    }
+
+    #[test]
+    fn passive_dns_prune_keeps_recently_resolved_addresses() {
+        let mut table = PassiveDnsTable::default();
+        for i in 0..10u64 {
+            let address = format!("192.0.2.{}", i);
+            table.add(address, format!("host{}.example", i), 1, i * 1_000_000);
+        }
+        // Un indirizzo vecchio risolto di nuovo torna tra i recenti.
+        let mut later = PassiveDnsTable::default();
+        later.add("192.0.2.0".to_string(), "host0.example".to_string(), 1, 20_000_000);
+        table.merge(later);
+
+        table.prune(3);
+        assert_eq!(table.addresses.len(), 3);
+        for kept in ["192.0.2.0", "192.0.2.8", "192.0.2.9"] {
+            assert!(table.hostname(kept).is_some(), "{}", kept);
+        }
+        table.prune(3);
+        assert_eq!(table.addresses.len(), 3);
+    }
+
+    #[test]
+    fn passive_dns_survives_save_and_reload() {
+        let dns = parse_dns_message(&cname_response(), false).unwrap();
+        let mut table = PassiveDnsTable::default();
+        table.record(&dns, 0);
+
+        let json = serde_json::to_string(&table).unwrap();
+        let reloaded: PassiveDnsTable = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.hostname("192.0.2.10").as_deref(), Some("www.example.com"));
+        assert!(load_passive_dns("/nonexistent/passive_dns.json").addresses.is_empty());
+    }
+}
@@ -0,0 +1,442 @@
+use crate::model::{
+    ApplicationProtocol, FlowRecord, PacketData, TcpFlowStats, TrafficCounter, TransportProtocol,
+    TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST, TCP_SYN,
+};
+use crate::tcp_helper::{TcpHealth, TcpSession};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+const TCP_FLAG_NAMES: [(u8, &str); 8] = [
+    (TCP_FIN, "FIN"),
+    (TCP_SYN, "SYN"),
+    (TCP_RST, "RST"),
+    (TCP_PSH, "PSH"),
+    (TCP_ACK, "ACK"),
+    (0x20, "URG"),
+    (0x40, "ECE"),
+    (0x80, "CWR"),
+];
+
+/// Chiave bidirezionale: i due endpoint sono ordinati così che entrambe le
+/// direzioni di una conversazione finiscano nello stesso flusso.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct FlowKey {
+    transport: Option<TransportProtocol>,
+    lower: (String, u16),
+    upper: (String, u16),
+}
+
+impl FlowKey {
+    fn from_packet(packet: &PacketData) -> Self {
+        let source = (packet.source_ip.clone(), packet.source_port);
+        let destination = (packet.destination_ip.clone(), packet.destination_port);
+        let (lower, upper) = if source <= destination {
+            (source, destination)
+        } else {
+            (destination, source)
+        };
+
+        FlowKey {
+            transport: packet.transport_layer.clone(),
+            lower,
+            upper,
+        }
+    }
+}
+
+/// Stato di un flusso durante l'analisi. I file ruotati iniziano a metà delle
+/// connessioni: il primo pacchetto non basta a riconoscere il client.
+struct FlowState {
+    client_ip: String,
+    client_port: u16,
+    server_ip: String,
+    server_port: u16,
+    application: Option<ApplicationProtocol>,
+    first_seen_us: u64,
+    last_seen_us: u64,
+    packets_to_server: u64,
+    bytes_to_server: u64,
+    packets_to_client: u64,
+    bytes_to_client: u64,
+    tcp_flags: u8,
+    tcp: Option<TcpSession>,
+}
+
+impl FlowState {
+    fn new(packet: &PacketData) -> Self {
+        let (client_ip, client_port, server_ip, server_port) = if source_is_client(packet) {
+            (&packet.source_ip, packet.source_port, &packet.destination_ip, packet.destination_port)
+        } else {
+            (&packet.destination_ip, packet.destination_port, &packet.source_ip, packet.source_port)
+        };
+        FlowState {
+            client_ip: client_ip.clone(),
+            client_port,
+            server_ip: server_ip.clone(),
+            server_port,
+            application: None,
+            first_seen_us: packet.timestamp_us,
+            last_seen_us: packet.timestamp_us,
+            packets_to_server: 0,
+            bytes_to_server: 0,
+            packets_to_client: 0,
+            bytes_to_client: 0,
+            tcp_flags: 0,
+            tcp: match packet.transport_layer {
+                Some(TransportProtocol::Tcp) => Some(TcpSession::default()),
+                _ => None,
+            },
+        }
+    }
+
+    fn update(&mut self, packet: &PacketData) {
+        let bytes = packet.packet_length as u64;
+        let to_server = packet.source_ip == self.client_ip && packet.source_port == self.client_port;
+        if to_server {
+            self.packets_to_server += 1;
+            self.bytes_to_server += bytes;
+        } else {
+            self.packets_to_client += 1;
+            self.bytes_to_client += bytes;
+        }
+
+        self.first_seen_us = self.first_seen_us.min(packet.timestamp_us);
+        self.last_seen_us = self.last_seen_us.max(packet.timestamp_us);
+        self.tcp_flags |= packet.tcp_flags.unwrap_or(0);
+        if self.application.is_none() {
+            self.application = packet.application_layer.clone();
+        }
+        if let (Some(session), Some(tcp), Some(flags)) = (&mut self.tcp, &packet.tcp, packet.tcp_flags) {
+            session.update(flags, tcp, to_server, packet.timestamp_us);
+        }
+    }
+
+    fn to_record(&self, transport: Option<TransportProtocol>) -> FlowRecord {
+        FlowRecord {
+            transport: transport.map(|t| t.to_string()),
+            application: self.application.as_ref().map(|a| a.to_string()),
+            client_ip: self.client_ip.clone(),
+            client_port: self.client_port,
+            server_ip: self.server_ip.clone(),
+            server_port: self.server_port,
+            client_hostname: None,
+            server_hostname: None,
+            first_seen_us: self.first_seen_us,
+            last_seen_us: self.last_seen_us,
+            duration_us: self.last_seen_us - self.first_seen_us,
+            packets_to_server: self.packets_to_server,
+            bytes_to_server: self.bytes_to_server,
+            packets_to_client: self.packets_to_client,
+            bytes_to_client: self.bytes_to_client,
+            tcp_flags: tcp_flag_names(self.tcp_flags),
+            tcp: self.tcp.as_ref().map(|session| session.to_stats()),
+        }
+    }
+}
+
+/// Il client è chi invia il SYN, mentre il SYN-ACK arriva dal server. Senza
+/// handshake il server è il lato con la porta registrata o, in mancanza, con
+/// la porta minore, come in `PortRegistry::classify`.
+fn source_is_client(packet: &PacketData) -> bool {
+    if let Some(flags) = packet.tcp_flags
+        && flags & TCP_SYN != 0
+    {
+        return flags & TCP_ACK == 0;
+    }
+    match packet.service_port {
+        Some(port) => port == packet.destination_port,
+        None => packet.destination_port <= packet.source_port,
+    }
+}
+
+fn tcp_flag_bits(names: &[String]) -> u8 {
+    TCP_FLAG_NAMES
+        .iter()
+        .filter(|(_, name)| names.iter().any(|n| n == name))
+        .fold(0, |flags, (bit, _)| flags | bit)
+}
+
+fn tcp_flag_names(flags: u8) -> Vec<String> {
+    TCP_FLAG_NAMES
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// Tabella dei flussi aggiornata pacchetto per pacchetto: la memoria
+/// occupata dipende dal numero di conversazioni, non di pacchetti.
+#[derive(Default)]
+pub struct FlowTable {
+    flows: HashMap<FlowKey, FlowState>,
+}
+
+impl FlowTable {
+    pub fn add_packet(&mut self, packet: &PacketData) {
+        self.flows
+            .entry(FlowKey::from_packet(packet))
+            .or_insert_with(|| FlowState::new(packet))
+            .update(packet);
+    }
+
+    /// Traffico per porta del server ("443/tcp"), sommando le due direzioni
+    /// dei flussi TCP e UDP: le porte effimere dei client non vengono contate.
+    pub fn server_ports(&self) -> HashMap<String, TrafficCounter> {
+        let mut ports: HashMap<String, TrafficCounter> = HashMap::new();
+        for (key, state) in self.flows.iter() {
+            let transport = match &key.transport {
+                Some(transport @ (TransportProtocol::Tcp | TransportProtocol::Udp)) => transport,
+                _ => continue,
+            };
+            let port_key = format!("{}/{}", state.server_port, transport.to_string().to_ascii_lowercase());
+            ports.entry(port_key).or_default().merge(&TrafficCounter {
+                packets: state.packets_to_server + state.packets_to_client,
+                bytes: state.bytes_to_server + state.bytes_to_client,
+            });
+        }
+        ports
+    }
+
+    pub fn tcp_health(&self) -> TcpHealth {
+        let mut health = TcpHealth::default();
+        for session in self.flows.values().filter_map(|state| state.tcp.as_ref()) {
+            health.add_session(session);
+        }
+        health
+    }
+
+    /// I `count` flussi con più byte, nello stesso ordine di `records`. La
+    /// selezione usa un heap limitato: i record vengono costruiti solo per
+    /// i flussi scelti.
+    pub fn top_records(&self, count: usize) -> Vec<FlowRecord> {
+        let mut heap: BinaryHeap<Reverse<RankedFlow>> = BinaryHeap::with_capacity(count + 1);
+        for (key, state) in self.flows.iter() {
+            heap.push(Reverse(RankedFlow { key, state }));
+            if heap.len() > count {
+                heap.pop();
+            }
+        }
+
+        let mut flows: Vec<FlowRecord> = heap
+            .into_iter()
+            .map(|Reverse(ranked)| ranked.state.to_record(ranked.key.transport.clone()))
+            .collect();
+        flows.sort_by(compare_records);
+        flows
+    }
+
+    /// Restituisce i flussi ordinati per volume totale di byte, dal maggiore.
+    pub fn records(&self) -> Vec<FlowRecord> {
+        let mut flows: Vec<FlowRecord> = self.flows
+            .iter()
+            .map(|(key, state)| state.to_record(key.transport.clone()))
+            .collect();
+        flows.sort_by(compare_records);
+        flows
+    }
+}
+
+/// Flusso candidato alla classifica: è "maggiore" chi ha più byte e, a
+/// parità, gli endpoint minori, come nell'ordinamento di `records`.
+struct RankedFlow<'a> {
+    key: &'a FlowKey,
+    state: &'a FlowState,
+}
+
+impl RankedFlow<'_> {
+    fn bytes(&self) -> u64 {
+        self.state.bytes_to_server + self.state.bytes_to_client
+    }
+
+    fn endpoints(&self) -> (&String, u16, &String, u16) {
+        (&self.state.client_ip, self.state.client_port, &self.state.server_ip, self.state.server_port)
+    }
+}
+
+impl Ord for RankedFlow<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bytes().cmp(&other.bytes())
+            .then_with(|| other.endpoints().cmp(&self.endpoints()))
+            .then_with(|| other.key.transport.as_ref().map(|t| t.to_string())
+                .cmp(&self.key.transport.as_ref().map(|t| t.to_string())))
+    }
+}
+
+impl PartialOrd for RankedFlow<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedFlow<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedFlow<'_> {}
+
+fn compare_records(a: &FlowRecord, b: &FlowRecord) -> Ordering {
+    (b.bytes_to_server + b.bytes_to_client)
+        .cmp(&(a.bytes_to_server + a.bytes_to_client))
+        .then_with(|| (&a.client_ip, a.client_port, &a.server_ip, a.server_port)
+            .cmp(&(&b.client_ip, b.client_port, &b.server_ip, b.server_port)))
+}
+
+/// Identità di un record indipendente dal lato scelto come client.
+fn record_identity(record: &FlowRecord) -> (Option<String>, (String, u16), (String, u16)) {
+    let client = (record.client_ip.clone(), record.client_port);
+    let server = (record.server_ip.clone(), record.server_port);
+    if client <= server {
+        (record.transport.clone(), client, server)
+    } else {
+        (record.transport.clone(), server, client)
+    }
+}
+
+/// Unisce i flussi candidati di un altro file mantenendo al massimo `count`
+/// voci ordinate per byte. Lo stesso flusso visto in più file viene
+/// sommato; un flusso escluso dai candidati non recupera quei conteggi.
+pub fn merge_top_flows(top: &mut Vec<FlowRecord>, other: Vec<FlowRecord>, count: usize) {
+    let mut index: HashMap<_, usize> = top.iter().enumerate().map(|(i, flow)| (record_identity(flow), i)).collect();
+    for record in other {
+        let identity = record_identity(&record);
+        match index.get(&identity) {
+            Some(&i) => merge_record(&mut top[i], record),
+            None => {
+                index.insert(identity, top.len());
+                top.push(record);
+            }
+        }
+    }
+    top.sort_by(compare_records);
+    top.truncate(count);
+}
+
+fn merge_record(flow: &mut FlowRecord, other: FlowRecord) {
+    if other.client_ip == flow.client_ip && other.client_port == flow.client_port {
+        flow.packets_to_server += other.packets_to_server;
+        flow.bytes_to_server += other.bytes_to_server;
+        flow.packets_to_client += other.packets_to_client;
+        flow.bytes_to_client += other.bytes_to_client;
+    } else {
+        flow.packets_to_server += other.packets_to_client;
+        flow.bytes_to_server += other.bytes_to_client;
+        flow.packets_to_client += other.packets_to_server;
+        flow.bytes_to_client += other.bytes_to_server;
+    }
+    flow.first_seen_us = flow.first_seen_us.min(other.first_seen_us);
+    flow.last_seen_us = flow.last_seen_us.max(other.last_seen_us);
+    flow.duration_us = flow.last_seen_us - flow.first_seen_us;
+    if flow.application.is_none() {
+        flow.application = other.application;
+    }
+    let flags = tcp_flag_bits(&flow.tcp_flags) | tcp_flag_bits(&other.tcp_flags);
+    flow.tcp_flags = tcp_flag_names(flags);
+    flow.tcp = match (flow.tcp.take(), other.tcp) {
+        (Some(tcp), Some(other_tcp)) => Some(merge_tcp_stats(tcp, other_tcp)),
+        (tcp, other_tcp) => tcp.or(other_tcp),
+    };
+}
+
+fn merge_tcp_stats(tcp: TcpFlowStats, other: TcpFlowStats) -> TcpFlowStats {
+    let closed_by = match (tcp.closed_by, other.closed_by) {
+        (Some(a), Some(b)) if a == "rst" || b == "rst" => Some("rst".to_string()),
+        (a, b) => a.or(b),
+    };
+    TcpFlowStats {
+        handshake_rtt_us: tcp.handshake_rtt_us.or(other.handshake_rtt_us),
+        segments: tcp.segments + other.segments,
+        retransmissions: tcp.retransmissions + other.retransmissions,
+        out_of_order: tcp.out_of_order + other.out_of_order,
+        zero_window_events: tcp.zero_window_events + other.zero_window_events,
+        closed_by,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const ACK: u8 = TCP_ACK;
+    const SYN: u8 = TCP_SYN;
+
+    fn tcp_packet(source: (&str, u16), destination: (&str, u16), flags: u8, length: usize) -> PacketData {
+        PacketData::tcp_segment(source, destination, flags, length, 0)
+    }
+
+    fn single_record(packets: &[PacketData]) -> FlowRecord {
+        let mut table = FlowTable::default();
+        for packet in packets {
+            table.add_packet(packet);
+        }
+        let mut records = table.records();
+        assert_eq!(records.len(), 1);
+        records.remove(0)
+    }
+
+    #[test]
+    fn syn_ack_seen_first_identifies_server() {
+        let record = single_record(&[
+            tcp_packet(("10.0.0.1", 443), ("10.0.0.2", 50000), SYN | ACK, 60),
+            tcp_packet(("10.0.0.2", 50000), ("10.0.0.1", 443), ACK, 54),
+        ]);
+        assert_eq!((record.server_ip.as_str(), record.server_port), ("10.0.0.1", 443));
+        assert_eq!((record.packets_to_server, record.bytes_to_server), (1, 54));
+        assert_eq!((record.packets_to_client, record.bytes_to_client), (1, 60));
+    }
+
+    #[test]
+    fn syn_source_is_client_even_from_low_port() {
+        let record = single_record(&[tcp_packet(("10.0.0.2", 80), ("10.0.0.1", 9000), SYN, 60)]);
+        assert_eq!((record.client_ip.as_str(), record.client_port), ("10.0.0.2", 80));
+        assert_eq!(record.server_port, 9000);
+    }
+
+    #[test]
+    fn mid_stream_flow_uses_lower_or_registered_port() {
+        let mut table = FlowTable::default();
+        table.add_packet(&tcp_packet(("10.0.0.1", 443), ("10.0.0.2", 51000), ACK, 1500));
+        let mut registered = tcp_packet(("10.0.0.3", 8080), ("10.0.0.4", 3000), ACK, 100);
+        registered.service_port = Some(8080);
+        table.add_packet(&registered);
+
+        let ports = table.server_ports();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports["443/tcp"].bytes, 1500);
+        assert_eq!(ports["8080/tcp"].bytes, 100);
+    }
+
+    #[test]
+    fn top_records_keeps_largest_flows_in_order() {
+        let mut table = FlowTable::default();
+        for (port, length) in [(1001, 100), (1002, 300), (1003, 200), (1004, 300)] {
+            table.add_packet(&tcp_packet(("10.0.0.2", port), ("10.0.0.1", 80), ACK, length));
+        }
+
+        let top = table.top_records(3);
+        let ports: Vec<u16> = top.iter().map(|flow| flow.client_port).collect();
+        assert_eq!(ports, vec![1002, 1004, 1003]);
+        assert_eq!(top, table.records()[..3].to_vec());
+    }
+
+    #[test]
+    fn merge_top_flows_sums_the_same_flow_seen_in_two_files() {
+        let first = single_record(&[tcp_packet(("10.0.0.2", 50000), ("10.0.0.1", 443), SYN, 60)]);
+        let other = tcp_packet(("10.0.0.2", 50001), ("10.0.0.1", 443), ACK, 10);
+        let mut second = single_record(&[tcp_packet(("10.0.0.1", 443), ("10.0.0.2", 50000), ACK, 1500)]);
+        second.client_ip = "10.0.0.1".to_string();
+        second.client_port = 443;
+        second.server_ip = "10.0.0.2".to_string();
+        second.server_port = 50000;
+        std::mem::swap(&mut second.bytes_to_server, &mut second.bytes_to_client);
+        std::mem::swap(&mut second.packets_to_server, &mut second.packets_to_client);
+
+        let mut top = vec![first];
+        merge_top_flows(&mut top, vec![second, single_record(&[other])], 1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].client_port, 50000);
+        assert_eq!((top[0].packets_to_server, top[0].bytes_to_server), (1, 60));
+        assert_eq!((top[0].packets_to_client, top[0].bytes_to_client), (1, 1500));
+        assert_eq!(top[0].tcp_flags, vec!["SYN", "ACK"]);
+    }
+}
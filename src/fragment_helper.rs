@@ -0,0 +1,357 @@
+use crate::model::{FragmentationStats, ReassemblyConfig};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Dimensione massima di un datagramma IP senza jumbogram.
+const MAX_DATAGRAM_LEN: usize = 65_535;
+
+/// Identifica un datagramma frammentato: per IPv4 l'ID è a 16 bit, per IPv6 a 32.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FragmentKey {
+    pub source_ip: String,
+    pub destination_ip: String,
+    pub id: u32,
+    pub protocol: u8,
+    pub ipv6: bool,
+}
+
+struct PendingDatagram {
+    first_seen_us: u64,
+    fragments: BTreeMap<usize, Vec<u8>>,
+    total_len: Option<usize>,
+    buffered_bytes: usize,
+    /// Datagramma IPv6 scartato per sovrapposizione (RFC 5722): resta in
+    /// tabella fino al timeout solo per ignorare i frammenti successivi.
+    discarded: bool,
+}
+
+impl PendingDatagram {
+    /// Intervalli di `[offset, end)` non ancora coperti da frammenti ricevuti.
+    fn gaps(&self, offset: usize, end: usize) -> Vec<(usize, usize)> {
+        let mut gaps = Vec::new();
+        let mut cursor = offset;
+        for (start, data) in self.fragments.range(..end) {
+            let stop = start + data.len();
+            if stop <= cursor {
+                continue;
+            }
+            if *start > cursor {
+                gaps.push((cursor, *start));
+            }
+            cursor = stop;
+        }
+        if cursor < end {
+            gaps.push((cursor, end));
+        }
+        gaps
+    }
+
+    /// Ricostruisce il payload se i frammenti coprono l'intero datagramma senza buchi.
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let total_len = self.total_len?;
+        let mut covered = 0;
+        for (offset, data) in self.fragments.range(..total_len) {
+            if *offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        if covered < total_len {
+            return None;
+        }
+
+        let mut datagram = vec![0u8; total_len];
+        for (offset, data) in self.fragments.range(..total_len) {
+            let end = (offset + data.len()).min(total_len);
+            datagram[*offset..end].copy_from_slice(&data[..end - offset]);
+        }
+        Some(datagram)
+    }
+}
+
+/// Riassemblaggio dei frammenti IP di una cattura. I datagrammi incompleti
+/// scadono dopo il timeout configurato e i limiti di memoria impediscono
+/// che una raffica di frammenti orfani esaurisca la RAM. In caso di
+/// sovrapposizione IPv4 vale il frammento arrivato per primo e si conserva
+/// solo la parte nuova; per IPv6 l'intero datagramma viene scartato come
+/// prescrive la RFC 5722.
+pub struct FragmentReassembler {
+    timeout_us: u64,
+    max_datagrams: usize,
+    max_buffered_bytes: usize,
+    now_us: u64,
+    pending: HashMap<FragmentKey, PendingDatagram>,
+    /// Datagrammi in attesa ordinati per primo frammento, per scadere senza
+    /// scorrere tutta la tabella.
+    expiry: BTreeSet<(u64, FragmentKey)>,
+    buffered_bytes: usize,
+    stats: FragmentationStats,
+}
+
+impl FragmentReassembler {
+    pub fn new(config: &ReassemblyConfig) -> Self {
+        FragmentReassembler {
+            timeout_us: config.timeout_secs * 1_000_000,
+            max_datagrams: config.max_datagrams,
+            max_buffered_bytes: config.max_buffered_bytes,
+            now_us: 0,
+            pending: HashMap::new(),
+            expiry: BTreeSet::new(),
+            buffered_bytes: 0,
+            stats: FragmentationStats::default(),
+        }
+    }
+
+    /// Aggiorna l'orologio al timestamp del pacchetto corrente e scarta i
+    /// datagrammi rimasti incompleti oltre il timeout.
+    pub fn advance_clock(&mut self, now_us: u64) {
+        self.now_us = self.now_us.max(now_us);
+        let deadline_us = self.now_us.saturating_sub(self.timeout_us);
+        while self.expiry.first().is_some_and(|(first_seen_us, _)| *first_seen_us < deadline_us)
+            && let Some((_, key)) = self.expiry.pop_first()
+        {
+            if let Some(datagram) = self.pending.remove(&key)
+                && !datagram.discarded
+            {
+                self.buffered_bytes -= datagram.buffered_bytes;
+                self.stats.timeouts += 1;
+            }
+        }
+    }
+
+    /// Aggiunge un frammento. Restituisce il payload completo del datagramma
+    /// quando questo frammento ne completa il riassemblaggio.
+    pub fn add(&mut self, key: FragmentKey, offset: usize, more_fragments: bool, payload: &[u8]) -> Option<Vec<u8>> {
+        self.stats.fragments += 1;
+        if offset + payload.len() > MAX_DATAGRAM_LEN
+            || self.buffered_bytes + payload.len() > self.max_buffered_bytes
+        {
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        if !self.pending.contains_key(&key) {
+            if self.pending.len() >= self.max_datagrams {
+                self.stats.dropped += 1;
+                return None;
+            }
+            self.stats.fragmented_datagrams += 1;
+            self.expiry.insert((self.now_us, key.clone()));
+        } else if let Some(datagram) = self.pending.get(&key) {
+            if datagram.discarded {
+                self.stats.dropped += 1;
+                return None;
+            }
+            if datagram.fragments.get(&offset).is_some_and(|data| data.as_slice() == payload) {
+                // Frammento duplicato identico: nessuna sovrapposizione da segnalare.
+                return None;
+            }
+        }
+        let now_us = self.now_us;
+        let datagram = self.pending.entry(key.clone()).or_insert_with(|| PendingDatagram {
+            first_seen_us: now_us,
+            fragments: BTreeMap::new(),
+            total_len: None,
+            buffered_bytes: 0,
+            discarded: false,
+        });
+
+        let end = offset + payload.len();
+        let gaps = datagram.gaps(offset, end);
+        if gaps != [(offset, end)] {
+            self.stats.overlaps += 1;
+            if key.ipv6 {
+                self.buffered_bytes -= datagram.buffered_bytes;
+                datagram.fragments.clear();
+                datagram.buffered_bytes = 0;
+                datagram.discarded = true;
+                return None;
+            }
+        }
+        for (start, stop) in gaps {
+            datagram.fragments.insert(start, payload[start - offset..stop - offset].to_vec());
+            datagram.buffered_bytes += stop - start;
+            self.buffered_bytes += stop - start;
+        }
+        if !more_fragments {
+            datagram.total_len = Some(end);
+        }
+
+        let assembled = datagram.assemble()?;
+        let released = datagram.buffered_bytes;
+        let first_seen_us = datagram.first_seen_us;
+        self.pending.remove(&key);
+        self.expiry.remove(&(first_seen_us, key));
+        self.buffered_bytes -= released;
+        self.stats.reassembled += 1;
+        Some(assembled)
+    }
+
+    /// Restituisce i contatori della cattura; i datagrammi ancora in attesa
+    /// vengono contati come incompleti.
+    pub fn finish(&mut self) -> FragmentationStats {
+        self.stats.incomplete += self.pending.values().filter(|datagram| !datagram.discarded).count() as u64;
+        self.pending.clear();
+        self.expiry.clear();
+        self.buffered_bytes = 0;
+        self.stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: u32) -> FragmentKey {
+        FragmentKey {
+            source_ip: "192.0.2.1".to_string(),
+            destination_ip: "198.51.100.1".to_string(),
+            id,
+            protocol: 17,
+            ipv6: false,
+        }
+    }
+
+    fn ipv6_key(id: u32) -> FragmentKey {
+        FragmentKey {
+            source_ip: "2001:db8::1".to_string(),
+            destination_ip: "2001:db8::2".to_string(),
+            id,
+            protocol: 17,
+            ipv6: true,
+        }
+    }
+
+    fn reassembler(timeout_secs: u64, max_datagrams: usize, max_buffered_bytes: usize) -> FragmentReassembler {
+        FragmentReassembler::new(&ReassemblyConfig { timeout_secs, max_datagrams, max_buffered_bytes })
+    }
+
+    /// Payload di `len` byte con valori crescenti, per riconoscere gli offset.
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn reassembles_fragments_in_any_order() {
+        let data = payload(24);
+        let mut fragments = FragmentReassembler::new(&ReassemblyConfig::default());
+        assert_eq!(fragments.add(key(1), 16, false, &data[16..]), None);
+        assert_eq!(fragments.add(key(1), 0, true, &data[..8]), None);
+        assert_eq!(fragments.add(key(1), 8, true, &data[8..16]), Some(data));
+
+        let stats = fragments.finish();
+        assert_eq!(stats.fragments, 3);
+        assert_eq!(stats.fragmented_datagrams, 1);
+        assert_eq!(stats.reassembled, 1);
+        assert_eq!(stats.incomplete, 0);
+    }
+
+    #[test]
+    fn identical_duplicate_is_not_an_overlap() {
+        let data = payload(16);
+        let mut fragments = FragmentReassembler::new(&ReassemblyConfig::default());
+        fragments.add(key(1), 0, true, &data[..8]);
+        fragments.add(key(1), 0, true, &data[..8]);
+        assert_eq!(fragments.add(key(1), 8, false, &data[8..]), Some(data));
+        assert_eq!(fragments.finish().overlaps, 0);
+    }
+
+    #[test]
+    fn first_fragment_wins_on_overlap() {
+        let data = payload(16);
+        let mut fragments = FragmentReassembler::new(&ReassemblyConfig::default());
+        fragments.add(key(1), 0, true, &data[..8]);
+        // Sovrappone gli ultimi 4 byte del primo frammento con contenuto diverso:
+        // si conservano solo i byte 8..12, ancora scoperti.
+        assert_eq!(fragments.add(key(1), 4, true, &[0xFF; 8]), None);
+        let mut expected = data[..8].to_vec();
+        expected.extend_from_slice(&[0xFF; 4]);
+        expected.extend_from_slice(&data[12..]);
+        assert_eq!(fragments.add(key(1), 12, false, &data[12..]), Some(expected));
+        assert_eq!(fragments.finish().overlaps, 1);
+    }
+
+    #[test]
+    fn overlapping_last_fragment_still_completes_ipv4() {
+        let data = payload(16);
+        let mut fragments = FragmentReassembler::new(&ReassemblyConfig::default());
+        fragments.add(key(1), 0, true, &data[..8]);
+        // Il frammento finale riparte da 4: la coda non sovrapposta completa il datagramma.
+        let mut last = vec![0xFF; 4];
+        last.extend_from_slice(&data[8..]);
+        assert_eq!(fragments.add(key(1), 4, false, &last), Some(data));
+
+        let stats = fragments.finish();
+        assert_eq!(stats.overlaps, 1);
+        assert_eq!(stats.reassembled, 1);
+        assert_eq!(stats.incomplete, 0);
+    }
+
+    #[test]
+    fn overlap_discards_ipv6_datagram() {
+        let data = payload(24);
+        let mut fragments = reassembler(30, 16, 1024);
+        fragments.add(ipv6_key(1), 0, true, &data[..16]);
+        assert_eq!(fragments.add(ipv6_key(1), 8, true, &data[8..16]), None);
+        assert_eq!(fragments.buffered_bytes, 0);
+        // I frammenti successivi dello stesso datagramma vengono ignorati.
+        assert_eq!(fragments.add(ipv6_key(1), 16, false, &data[16..]), None);
+        assert_eq!(fragments.add(ipv6_key(1), 0, true, &data[..16]), None);
+
+        fragments.advance_clock(60_000_000);
+        let stats = fragments.finish();
+        assert_eq!(stats.overlaps, 1);
+        assert_eq!(stats.dropped, 2);
+        assert_eq!(stats.reassembled, 0);
+        assert_eq!(stats.timeouts, 0);
+        assert_eq!(stats.incomplete, 0);
+    }
+
+    #[test]
+    fn incomplete_datagrams_time_out() {
+        let data = payload(16);
+        let mut fragments = reassembler(30, 16, 1024);
+        fragments.advance_clock(1_000_000);
+        fragments.add(key(1), 0, true, &data[..8]);
+        fragments.advance_clock(20_000_000);
+        fragments.add(key(2), 0, true, &data[..8]);
+
+        fragments.advance_clock(40_000_000);
+        assert_eq!(fragments.add(key(1), 8, false, &data[8..]), None);
+        assert_eq!(fragments.add(key(2), 8, false, &data[8..]), Some(data));
+
+        let stats = fragments.finish();
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.reassembled, 1);
+        // Il frammento finale di key(1) apre un nuovo datagramma mai completato.
+        assert_eq!(stats.incomplete, 1);
+    }
+
+    #[test]
+    fn completed_datagrams_leave_nothing_to_expire() {
+        let data = payload(16);
+        let mut fragments = reassembler(30, 16, 1024);
+        fragments.add(key(1), 0, true, &data[..8]);
+        fragments.add(key(1), 8, false, &data[8..]);
+        assert!(fragments.expiry.is_empty());
+        fragments.advance_clock(60_000_000);
+        assert_eq!(fragments.finish().timeouts, 0);
+    }
+
+    #[test]
+    fn limits_drop_excess_fragments() {
+        let data = payload(16);
+        let mut fragments = reassembler(30, 1, 12);
+        fragments.add(key(1), 0, true, &data[..8]);
+        // Oltre il numero di datagrammi in attesa.
+        assert_eq!(fragments.add(key(2), 0, true, &data[..8]), None);
+        // Oltre i byte trattenuti.
+        assert_eq!(fragments.add(key(1), 8, true, &data[8..]), None);
+        // Oltre la dimensione massima di un datagramma.
+        assert_eq!(fragments.add(key(1), MAX_DATAGRAM_LEN - 4, false, &data[..8]), None);
+
+        let stats = fragments.finish();
+        assert_eq!(stats.dropped, 3);
+        assert_eq!(stats.fragmented_datagrams, 1);
+        assert_eq!(stats.incomplete, 1);
+    }
+}
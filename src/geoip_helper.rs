@@ -0,0 +1,133 @@
+use crate::model::{GeoInfo, GeoIpConfig};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+
+const CSV_FIELDS: usize = 6;
+const NAME_LANGUAGE: &str = "en";
+
+/// Intervallo di indirizzi del database CSV, estremi inclusi.
+struct GeoIpRange {
+    start: IpAddr,
+    end: IpAddr,
+    info: GeoInfo,
+}
+
+/// Database locali per l'arricchimento geografico: i file MMDB (GeoLite2
+/// City/Country e ASN) e un CSV di intervalli che completa i campi mancanti.
+pub struct GeoIpDatabase {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+    ranges: Vec<GeoIpRange>,
+}
+
+/// Carica i database configurati. Restituisce `None` se non ne è
+/// configurato nessuno, così l'arricchimento viene saltato del tutto.
+pub fn load_geoip(config: &GeoIpConfig) -> Result<Option<GeoIpDatabase>, Box<dyn Error>> {
+    if config.city_database.is_none() && config.asn_database.is_none() && config.csv_database.is_none() {
+        return Ok(None);
+    }
+
+    let open_mmdb = |path: &String| {
+        Reader::open_readfile(path).map_err(|e| format!("{} nell'apertura del database GeoIP {}", e, path))
+    };
+    let city = config.city_database.as_ref().map(open_mmdb).transpose()?;
+    let asn = config.asn_database.as_ref().map(open_mmdb).transpose()?;
+    let ranges = match &config.csv_database {
+        Some(path) => load_csv_ranges(path)?,
+        None => Vec::new(),
+    };
+
+    Ok(Some(GeoIpDatabase { city, asn, ranges }))
+}
+
+/// Formato: start_ip,end_ip,country,city,asn,organization. Le righe vuote,
+/// i commenti (#) e un'eventuale intestazione vengono ignorati; l'ultima
+/// colonna può contenere virgole.
+fn load_csv_ranges(path: &str) -> Result<Vec<GeoIpRange>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("{} nella lettura del database GeoIP {}", e, path))?;
+    let mut ranges = Vec::new();
+
+    for (line_number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.splitn(CSV_FIELDS, ',').map(|f| f.trim().trim_matches('"')).collect();
+        if fields.len() < 2 {
+            return Err(format!("Riga {} non valida nel database GeoIP {}", line_number + 1, path).into());
+        }
+        let (start, end) = match (fields[0].parse::<IpAddr>(), fields[1].parse::<IpAddr>()) {
+            (Ok(start), Ok(end)) if start.is_ipv4() == end.is_ipv4() && start <= end => (start, end),
+            _ if line_number == 0 => continue,
+            _ => return Err(format!("Intervallo non valido alla riga {} del database GeoIP {}", line_number + 1, path).into()),
+        };
+        let field = |index: usize| fields.get(index).filter(|f| !f.is_empty()).map(|f| f.to_string());
+
+        ranges.push(GeoIpRange {
+            start,
+            end,
+            info: GeoInfo {
+                country: field(2),
+                city: field(3),
+                asn: field(4).and_then(|asn| asn.trim_start_matches("AS").parse().ok()),
+                organization: field(5),
+            },
+        });
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    Ok(ranges)
+}
+
+impl GeoIpDatabase {
+    pub fn lookup(&self, ip: &IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+
+        if let Some(reader) = &self.city
+            && let Some(city) = ignore_not_found(reader.lookup::<geoip2::City>(*ip))
+        {
+            info.country = city.country.and_then(|country| country.iso_code).map(|code| code.to_string());
+            info.city = city.city
+                .and_then(|city| city.names)
+                .and_then(|names| names.get(NAME_LANGUAGE).map(|name| name.to_string()));
+        }
+
+        if let Some(reader) = &self.asn
+            && let Some(asn) = ignore_not_found(reader.lookup::<geoip2::Asn>(*ip))
+        {
+            info.asn = asn.autonomous_system_number;
+            info.organization = asn.autonomous_system_organization.map(|org| org.to_string());
+        }
+
+        if let Some(range) = self.find_range(ip) {
+            info.country = info.country.or_else(|| range.info.country.clone());
+            info.city = info.city.or_else(|| range.info.city.clone());
+            info.asn = info.asn.or(range.info.asn);
+            info.organization = info.organization.or_else(|| range.info.organization.clone());
+        }
+
+        info
+    }
+
+    fn find_range(&self, ip: &IpAddr) -> Option<&GeoIpRange> {
+        let index = self.ranges.partition_point(|range| range.start <= *ip);
+        let range = self.ranges.get(index.checked_sub(1)?)?;
+        if *ip <= range.end { Some(range) } else { None }
+    }
+}
+
+/// Un indirizzo assente dal database non è un errore: resta senza dati.
+fn ignore_not_found<T>(result: Result<T, MaxMindDBError>) -> Option<T> {
+    match result {
+        Ok(record) => Some(record),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+        Err(e) => {
+            eprintln!("Errore nella ricerca GeoIP: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::model::HttpInfo;
+
+const HTTP_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE"];
+const HTTP_VERSION_PREFIX: &str = "HTTP/1.";
+const MAX_HEADER_LINES: usize = 100;
+
+/// Riconosce una request line o una status line HTTP/1.x all'inizio del
+/// payload TCP e ne estrae gli header di interesse. I segmenti successivi
+/// al primo (corpo, header spezzati) non vengono riconosciuti.
+pub fn parse_http(payload: &[u8]) -> Option<HttpInfo> {
+    let first_line_end = payload.windows(2).position(|w| w == b"\r\n")?;
+    let first_line = std::str::from_utf8(&payload[..first_line_end]).ok()?;
+    let mut parts = first_line.splitn(3, ' ');
+    let first = parts.next()?;
+    let second = parts.next()?;
+    let third = parts.next().unwrap_or("");
+
+    let mut info = HttpInfo {
+        method: None,
+        host: None,
+        path: None,
+        user_agent: None,
+        status_code: None,
+    };
+
+    if first.starts_with(HTTP_VERSION_PREFIX) {
+        info.status_code = Some(second.parse().ok()?);
+    } else if HTTP_METHODS.contains(&first) && third.starts_with(HTTP_VERSION_PREFIX) {
+        info.method = Some(first.to_string());
+        info.path = Some(second.split('?').next().unwrap_or(second).to_string());
+    } else {
+        return None;
+    }
+
+    let headers = String::from_utf8_lossy(&payload[first_line_end + 2..]);
+    for line in headers.split("\r\n").take(MAX_HEADER_LINES) {
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("host") {
+                info.host = Some(value.to_ascii_lowercase());
+            } else if name.eq_ignore_ascii_case("user-agent") {
+                info.user_agent = Some(value.to_string());
+            }
+        }
+    }
+
+    Some(info)
+}
+
+/// Raggruppa gli status code per classe (2xx, 3xx, ...).
+pub fn status_class(status_code: u16) -> String {
+    format!("{}xx", status_code / 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let payload = b"GET /index.html?q=1 HTTP/1.1\r\nHost: Example.COM\r\nUser-Agent: curl/8.0\r\nAccept: */*\r\n\r\n";
+        let info = parse_http(payload).unwrap();
+        assert_eq!(info.method.as_deref(), Some("GET"));
+        assert_eq!(info.path.as_deref(), Some("/index.html"));
+        assert_eq!(info.host.as_deref(), Some("example.com"));
+        assert_eq!(info.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(info.status_code, None);
+    }
+
+    #[test]
+    fn parses_status_line() {
+        let info = parse_http(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert_eq!(info.status_code, Some(404));
+        assert_eq!(info.method, None);
+        assert_eq!(status_class(404), "4xx");
+    }
+
+    #[test]
+    fn headers_stop_at_blank_line() {
+        let payload = b"POST /upload HTTP/1.0\r\n\r\nHost: body.example\r\n";
+        let info = parse_http(payload).unwrap();
+        assert_eq!(info.method.as_deref(), Some("POST"));
+        assert_eq!(info.host, None);
+    }
+
+    #[test]
+    fn rejects_non_http_and_truncated_payloads() {
+        assert!(parse_http(b"SSH-2.0-OpenSSH_9.6\r\n").is_none());
+        assert!(parse_http(b"FOO / HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_http(b"GET / SPDY/3\r\n\r\n").is_none());
+        assert!(parse_http(b"HTTP/1.1 abc OK\r\n\r\n").is_none());
+        // Request line interrotta prima del CRLF.
+        assert!(parse_http(b"GET /index.html HTT").is_none());
+        assert!(parse_http(b"").is_none());
+        assert!(parse_http(&[0x16, 0x03, 0x01, 0x00, 0x05, b'\r', b'\n']).is_none());
+    }
+}
@@ -12,7 +12,7 @@ pub fn dispatch_jobs(
     let mut index = 0;
 
     for pcap_path in watcher_rx {
-        if pcap_path.extension().and_then(|e| e.to_str()) == Some("pcap") {
+        if crate::util::is_capture_file(&pcap_path) {
             let path_str = pcap_path.to_string_lossy().to_string();
 
             if seen_files.insert(path_str.clone()) {
@@ -32,7 +32,7 @@ pub fn dispatch_jobs(
 
 fn path_builder(pcap_path: &Path, output_dir: &str) -> Option<(String, String)> {
     let input_path = pcap_path.to_str()?.to_string();
-    let file_name = pcap_path.file_name()?.to_str().unwrap_or("unknown_file");
+    let file_name = crate::util::capture_name(pcap_path).unwrap_or("unknown_file");
     let output_path = PathBuf::from(output_dir)
         .join(format!("{}.json", file_name))
         .to_str()?
@@ -3,6 +3,19 @@ mod service;
 mod model;
 mod network_capture;
 mod pcap_helper;
+mod fragment_helper;
+mod pcapng_reader;
+mod pcap_file_reader;
+mod tunnel_helper;
+mod port_registry;
+mod tls_helper;
+mod dns_helper;
+mod http_helper;
+mod tcp_helper;
+mod flow_helper;
+mod detection_helper;
+mod subnet_helper;
+mod geoip_helper;
 mod stat_helper;
 mod job_dispatcher;
 mod thread;
@@ -10,5 +23,8 @@ mod thread;
 fn main() {
     let config: model::Config = service::load_config();
     println!("Configurazione caricata: {:?}", config.output_dir);
-    let _ = service::monitor_network(config);
+    if let Err(e) = service::monitor_network(config) {
+        eprintln!("Errore nell'avvio del monitoraggio: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -1,23 +1,246 @@
 use std::collections::HashMap;
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub watch_dir: String,
     pub output_dir: String,
     pub parallelism: i8,
+    #[serde(default)]
+    pub decapsulation: DecapsulationConfig,
+    #[serde(default)]
+    pub classification: ClassificationConfig,
+    #[serde(default)]
+    pub export_flows: bool,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub geoip: GeoIpConfig,
+    #[serde(default)]
+    pub reassembly: ReassemblyConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StatsConfig {
+    pub time_bucket_secs: u64,
+    /// Intervalli della serie temporale conservati in `total_stats.json`:
+    /// oltre il limite l'aggregatore scarta i più vecchi.
+    pub max_time_buckets: usize,
+    pub top_n: usize,
+    /// Chiavi distinte (indirizzi, conversazioni, nomi) conservate
+    /// dall'aggregatore per ogni classifica, flussi candidati alla
+    /// classifica dei flussi e indirizzi della tabella passive DNS. È un'approssimazione: una mappa che supera
+    /// `4 * max_tracked_keys` chiavi perde le meno rilevanti con i loro
+    /// conteggi e dei flussi restano solo i maggiori, quindi le classifiche
+    /// possono differire da un ricalcolo completo. I totali restano esatti.
+    pub max_tracked_keys: usize,
+    /// Zone di rete con nome, ciascuna formata da una o più reti CIDR. Senza
+    /// zone `by_zone` e `zone_matrix` restano vuoti. Esempio:
+    /// `{"dmz": ["192.168.100.0/24"], "datacenter": ["10.0.0.0/16", "fd00:10::/48"]}`.
+    pub zones: HashMap<String, Vec<IpNetwork>>,
+    /// Reti considerate interne per il calcolo della direzione del traffico.
+    pub home_networks: Vec<IpNetwork>,
+    /// Aggiunge alle reti interne gli spazi privati RFC1918 e gli ULA IPv6.
+    pub private_networks_are_home: bool,
+    pub detection: DetectionConfig,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            time_bucket_secs: 60,
+            max_time_buckets: 1440,
+            top_n: 10,
+            max_tracked_keys: 10_000,
+            zones: HashMap::new(),
+            home_networks: Vec::new(),
+            private_networks_are_home: true,
+            detection: DetectionConfig::default(),
+        }
+    }
+}
+
+/// Soglie oltre le quali una sorgente viene segnalata come ricognizione.
+/// Il rilevamento considera solo i tentativi di connessione TCP: le
+/// scansioni UDP non vengono segnalate.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DetectionConfig {
+    /// Porte distinte sondate su un singolo host.
+    pub vertical_scan_ports: usize,
+    /// Host distinti sondati sulla stessa porta.
+    pub horizontal_sweep_hosts: usize,
+    /// Tentativi di connessione TCP rimasti senza risposta.
+    pub unanswered_syns: usize,
+    /// Durata in secondi della finestra entro cui si applicano le soglie. Le
+    /// finestre si sovrappongono per metà della loro durata.
+    pub window_secs: u64,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        DetectionConfig {
+            vertical_scan_ports: 100,
+            horizontal_sweep_hosts: 50,
+            unanswered_syns: 100,
+            window_secs: 300,
+        }
+    }
+}
+
+/// Limiti del riassemblaggio dei frammenti IP, applicati per singolo file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReassemblyConfig {
+    /// Secondi oltre i quali un datagramma incompleto viene scartato.
+    pub timeout_secs: u64,
+    /// Datagrammi in attesa di riassemblaggio contemporaneamente.
+    pub max_datagrams: usize,
+    /// Byte di frammenti trattenuti in memoria in attesa di riassemblaggio.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        ReassemblyConfig {
+            timeout_secs: 30,
+            max_datagrams: 1024,
+            max_buffered_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Rete in notazione CIDR ("10.0.0.0/8"). Senza prefisso indica un singolo
+/// host. L'indirizzo viene sempre normalizzato sul prefisso.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNetwork {
+    pub address: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Self> {
+        let address = match address {
+            IpAddr::V4(v4) if prefix_len <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                IpAddr::V4((u32::from(v4) & mask).into())
+            }
+            IpAddr::V6(v6) if prefix_len <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                IpAddr::V6((u128::from(v6) & mask).into())
+            }
+            _ => return None,
+        };
+        Some(IpNetwork { address, prefix_len })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match IpNetwork::new(*ip, self.prefix_len) {
+            Some(network) => network.address == self.address,
+            None => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.trim().parse()
+            .map_err(|e| format!("{} nella rete {}", e, value))?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.trim().parse()
+                .map_err(|e| format!("{} nel prefisso della rete {}", e, value))?,
+            None if address.is_ipv4() => 32,
+            None => 128,
+        };
+        IpNetwork::new(address, prefix_len).ok_or_else(|| format!("Prefisso non valido nella rete {}", value))
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl Serialize for IpNetwork {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Percorsi dei database GeoIP locali: MMDB in formato GeoLite2 (City o
+/// Country, ASN) e/o un CSV di intervalli di indirizzi.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GeoIpConfig {
+    pub city_database: Option<String>,
+    pub asn_database: Option<String>,
+    pub csv_database: Option<String>,
+}
+
+/// Le chiavi di `ports` seguono il formato di /etc/services ("8080/tcp")
+/// e hanno la precedenza sulle voci del `services_file` e sui default.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ClassificationConfig {
+    pub services_file: Option<String>,
+    pub ports: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DecapsulationConfig {
+    pub enabled: bool,
+    pub max_depth: u8,
+    pub vxlan_ports: Vec<u16>,
+    pub geneve_ports: Vec<u16>,
+}
+
+impl Default for DecapsulationConfig {
+    fn default() -> Self {
+        DecapsulationConfig {
+            enabled: true,
+            max_depth: 4,
+            vxlan_ports: vec![4789],
+            geneve_ports: vec![6081],
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone)] 
 pub enum InternetProtocol {
     IPv4,
+    IPv6,
 }
 
 impl fmt::Display for InternetProtocol {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             InternetProtocol::IPv4 => write!(f, "IPv4"),
+            InternetProtocol::IPv6 => write!(f, "IPv6"),
         }
     }
 }
@@ -26,6 +249,9 @@ impl fmt::Display for InternetProtocol {
 pub enum TransportProtocol {
     Tcp,
     Udp,
+    Icmp,
+    Icmpv6,
+    Other(u8),
 }
 
 impl fmt::Display for TransportProtocol {
@@ -33,6 +259,9 @@ impl fmt::Display for TransportProtocol {
         match self {
             TransportProtocol::Tcp => write!(f, "Tcp"),
             TransportProtocol::Udp => write!(f, "Udp"),
+            TransportProtocol::Icmp => write!(f, "Icmp"),
+            TransportProtocol::Icmpv6 => write!(f, "Icmpv6"),
+            TransportProtocol::Other(protocol) => write!(f, "IpProto{}", protocol),
         }
     }
 }
@@ -41,6 +270,27 @@ impl fmt::Display for TransportProtocol {
 pub enum ApplicationProtocol {
     Dns, 
     Http, 
+    Tls,
+    Ftp,
+    Ssh,
+    Telnet,
+    Smtp,
+    Pop3,
+    Imap,
+    Ntp,
+    Dhcp,
+    Snmp,
+    Ldap,
+    Kerberos,
+    Rdp,
+    Smb,
+    NetBios,
+    Syslog,
+    Sip,
+    Bgp,
+    Mysql,
+    Postgres,
+    Other(String),
 }
 
 impl fmt::Display for ApplicationProtocol {
@@ -48,10 +298,134 @@ impl fmt::Display for ApplicationProtocol {
         match self {
             ApplicationProtocol::Dns => write!(f, "Dns"),
             ApplicationProtocol::Http => write!(f, "Http"),
+            ApplicationProtocol::Tls => write!(f, "Tls"),
+            ApplicationProtocol::Ftp => write!(f, "Ftp"),
+            ApplicationProtocol::Ssh => write!(f, "Ssh"),
+            ApplicationProtocol::Telnet => write!(f, "Telnet"),
+            ApplicationProtocol::Smtp => write!(f, "Smtp"),
+            ApplicationProtocol::Pop3 => write!(f, "Pop3"),
+            ApplicationProtocol::Imap => write!(f, "Imap"),
+            ApplicationProtocol::Ntp => write!(f, "Ntp"),
+            ApplicationProtocol::Dhcp => write!(f, "Dhcp"),
+            ApplicationProtocol::Snmp => write!(f, "Snmp"),
+            ApplicationProtocol::Ldap => write!(f, "Ldap"),
+            ApplicationProtocol::Kerberos => write!(f, "Kerberos"),
+            ApplicationProtocol::Rdp => write!(f, "Rdp"),
+            ApplicationProtocol::Smb => write!(f, "Smb"),
+            ApplicationProtocol::NetBios => write!(f, "NetBios"),
+            ApplicationProtocol::Syslog => write!(f, "Syslog"),
+            ApplicationProtocol::Sip => write!(f, "Sip"),
+            ApplicationProtocol::Bgp => write!(f, "Bgp"),
+            ApplicationProtocol::Mysql => write!(f, "Mysql"),
+            ApplicationProtocol::Postgres => write!(f, "Postgres"),
+            ApplicationProtocol::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Tabella porta -> protocollo applicativo, separata per TCP e UDP.
+#[derive(Debug, Clone, Default)]
+pub struct PortRegistry {
+    pub tcp: HashMap<u16, ApplicationProtocol>,
+    pub udp: HashMap<u16, ApplicationProtocol>,
+}
+
+/// Opzioni condivise dai worker per la decodifica dei pacchetti.
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    pub decapsulation: DecapsulationConfig,
+    pub port_registry: PortRegistry,
+    pub reassembly: ReassemblyConfig,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone)]
+pub enum Encapsulation {
+    Gre,
+    Vxlan,
+    Geneve,
+    IpInIp,
+    Erspan,
+}
+
+impl fmt::Display for Encapsulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encapsulation::Gre => write!(f, "Gre"),
+            Encapsulation::Vxlan => write!(f, "Vxlan"),
+            Encapsulation::Geneve => write!(f, "Geneve"),
+            Encapsulation::IpInIp => write!(f, "IpInIp"),
+            Encapsulation::Erspan => write!(f, "Erspan"),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Tunnel {
+    pub encapsulation: Encapsulation,
+    pub outer_source_ip: String,
+    pub outer_destination_ip: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct IcmpInfo {
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub query_type: u16,
+}
+
+/// Record della sezione answer utili al passive DNS.
+#[derive(Debug, Clone)]
+pub enum DnsAnswer {
+    Address { name: String, address: IpAddr },
+    Cname { name: String, target: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsInfo {
+    pub is_response: bool,
+    pub response_code: u8,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsAnswer>,
+}
+
+/// Campi dell'header TCP usati per l'analisi delle sessioni. `payload_len`
+/// deriva dalla lunghezza dichiarata nell'header IP, non da quella catturata.
+#[derive(Debug, Clone)]
+pub struct TcpInfo {
+    pub sequence: u32,
+    pub acknowledgment: u32,
+    pub window: u16,
+    pub payload_len: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpInfo {
+    pub method: Option<String>,
+    pub host: Option<String>,
+    pub path: Option<String>,
+    pub user_agent: Option<String>,
+    pub status_code: Option<u16>,
+}
+
+/// Bit dei flag TCP nell'header e in `PacketData::tcp_flags`.
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
 #[derive(Debug)] 
 pub struct PacketData {
     pub internet_layer: InternetProtocol, 
@@ -61,10 +435,68 @@ pub struct PacketData {
     pub destination_ip: String,
     pub source_port: u16,
     pub destination_port: u16,
+    /// Porta registrata nel `PortRegistry`, usata per riconoscere il lato server.
+    pub service_port: Option<u16>,
     pub packet_length: usize,
+    pub captured_length: usize,
+    pub interface: Option<String>,
+    pub vlan_ids: Vec<u16>,
+    pub tunnels: Vec<Tunnel>,
+    pub icmp: Option<IcmpInfo>,
+    pub tls: Option<TlsInfo>,
+    pub dns: Option<DnsInfo>,
+    pub http: Option<HttpInfo>,
+    pub tcp_flags: Option<u8>,
+    pub tcp: Option<TcpInfo>,
+    pub timestamp_us: u64,
+}
+
+impl PacketData {
+    /// Pacchetto con i soli dati del livello IP, completato dai mapper successivi.
+    pub fn new(internet_layer: InternetProtocol, source_ip: String, destination_ip: String, packet_len: usize) -> Self {
+        PacketData {
+            internet_layer,
+            transport_layer: None,
+            application_layer: None,
+            source_ip,
+            destination_ip,
+            source_port: 0,
+            destination_port: 0,
+            service_port: None,
+            packet_length: packet_len,
+            captured_length: packet_len,
+            interface: None,
+            vlan_ids: Vec::new(),
+            tunnels: Vec::new(),
+            icmp: None,
+            tls: None,
+            dns: None,
+            http: None,
+            tcp_flags: None,
+            tcp: None,
+            timestamp_us: 0,
+        }
+    }
+
+    /// Segmento TCP IPv4 per i test di flussi, sessioni e rilevamento.
+    #[cfg(test)]
+    pub fn tcp_segment(source: (&str, u16), destination: (&str, u16), flags: u8, length: usize, timestamp_us: u64) -> Self {
+        let mut packet = PacketData::new(InternetProtocol::IPv4, source.0.to_string(), destination.0.to_string(), length);
+        packet.transport_layer = Some(TransportProtocol::Tcp);
+        packet.source_port = source.1;
+        packet.destination_port = destination.1;
+        packet.tcp_flags = Some(flags);
+        packet.timestamp_us = timestamp_us;
+        packet
+    }
+
+    /// Solo TCP e UDP hanno porte; per gli altri protocolli valgono 0.
+    pub fn has_ports(&self) -> bool {
+        matches!(self.transport_layer, Some(TransportProtocol::Tcp) | Some(TransportProtocol::Udp))
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Hash, Deserialize, Clone)]
 pub enum ProtocolKey {
     Internet(InternetProtocol),
     Transport(TransportProtocol),
@@ -90,11 +522,322 @@ impl Serialize for ProtocolKey {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct TrafficCounter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl TrafficCounter {
+    pub fn add(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+
+    pub fn merge(&mut self, other: &TrafficCounter) {
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Direzione di un pacchetto rispetto alle reti interne.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrafficDirection {
+    Inbound,
+    Outbound,
+    Internal,
+    ExternalTransit,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct DirectionTraffic {
+    pub packets: u64,
+    pub bytes: u64,
+    /// Endpoint esterni più attivi: la sorgente per il traffico in ingresso,
+    /// la destinazione per quello in uscita, entrambi per il transito.
+    pub top_remote_peers: TopRanking<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct DirectionStats {
+    pub inbound: DirectionTraffic,
+    pub outbound: DirectionTraffic,
+    pub internal: DirectionTraffic,
+    pub external_transit: DirectionTraffic,
+}
+
+impl DirectionStats {
+    pub fn get_mut(&mut self, direction: TrafficDirection) -> &mut DirectionTraffic {
+        match direction {
+            TrafficDirection::Inbound => &mut self.inbound,
+            TrafficDirection::Outbound => &mut self.outbound,
+            TrafficDirection::Internal => &mut self.internal,
+            TrafficDirection::ExternalTransit => &mut self.external_transit,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub organization: Option<String>,
+}
+
+impl GeoInfo {
+    /// Chiave per il raggruppamento per ASN ("AS13335 Cloudflare, Inc.").
+    pub fn asn_key(&self) -> Option<String> {
+        let asn = self.asn?;
+        Some(match &self.organization {
+            Some(organization) => format!("AS{} {}", asn, organization),
+            None => format!("AS{}", asn),
+        })
+    }
+}
+
+/// Voce di una classifica. Le percentuali sono riferite al totale di
+/// pacchetti e byte della cattura (o della direzione, per i peer remoti):
+/// un indirizzo che compare sia come sorgente sia come destinazione viene
+/// contato una volta per pacchetto.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TopEntry<K> {
+    pub key: K,
+    pub packets: u64,
+    pub bytes: u64,
+    pub packets_percentage: f64,
+    pub bytes_percentage: f64,
+    /// Presente solo per gli indirizzi IP quando è configurato un database GeoIP.
+    pub geo: Option<GeoInfo>,
+    /// Nome risolto per l'indirizzo secondo il passive DNS; per le
+    /// conversazioni riporta "nome -> nome".
+    pub hostname: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct TopRanking<K> {
+    pub by_packets: Vec<TopEntry<K>>,
+    pub by_bytes: Vec<TopEntry<K>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct TunnelStats {
+    pub by_encapsulation: HashMap<Encapsulation, TrafficCounter>,
+    pub top_10_endpoints: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct TlsStats {
+    pub client_hellos: u64,
+    pub top_10_server_names: Vec<String>,
+    pub by_version: HashMap<String, u32>,
+    pub by_alpn: HashMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct DnsStats {
+    pub queries: u64,
+    pub responses: u64,
+    pub top_10_queried_names: Vec<String>,
+    pub by_query_type: HashMap<String, u32>,
+    pub by_response_code: HashMap<String, u32>,
+    pub nxdomain_ratio: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct HttpStats {
+    pub requests: u64,
+    pub responses: u64,
+    pub top_10_hosts: Vec<String>,
+    pub top_10_paths: Vec<String>,
+    pub top_10_user_agents: Vec<String>,
+    pub by_method: HashMap<String, u32>,
+    pub by_status_class: HashMap<String, u32>,
+}
+
+/// Conversazione bidirezionale: il server è la destinazione del SYN o, se
+/// l'apertura non è stata osservata, il lato con la porta registrata o minore.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct FlowRecord {
+    pub transport: Option<String>,
+    pub application: Option<String>,
+    pub client_ip: String,
+    pub client_port: u16,
+    pub server_ip: String,
+    pub server_port: u16,
+    pub client_hostname: Option<String>,
+    pub server_hostname: Option<String>,
+    pub first_seen_us: u64,
+    pub last_seen_us: u64,
+    pub duration_us: u64,
+    pub packets_to_server: u64,
+    pub bytes_to_server: u64,
+    pub packets_to_client: u64,
+    pub bytes_to_client: u64,
+    pub tcp_flags: Vec<String>,
+    pub tcp: Option<TcpFlowStats>,
+}
+
+/// Analisi di una sessione TCP. `closed_by` vale "rst" o "fin" se la
+/// chiusura è stata osservata.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct TcpFlowStats {
+    pub handshake_rtt_us: Option<u64>,
+    pub segments: u64,
+    pub retransmissions: u64,
+    pub out_of_order: u64,
+    pub zero_window_events: u64,
+    pub closed_by: Option<String>,
+}
+
+/// Metriche di salute TCP: i flussi chiusi da RST non sono contati fra
+/// quelli chiusi da FIN anche se hanno entrambi i flag.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct TcpHealthStats {
+    pub flows: u64,
+    pub handshakes_completed: u64,
+    pub avg_handshake_rtt_us: f64,
+    pub max_handshake_rtt_us: Option<u64>,
+    pub segments: u64,
+    pub retransmissions: u64,
+    pub retransmission_ratio: f64,
+    pub out_of_order: u64,
+    pub reset_flows: u64,
+    pub fin_flows: u64,
+    pub zero_window_events: u64,
+}
+
+/// Contatori della frammentazione IP. I frammenti scartati per limiti di
+/// memoria o dimensione non valida finiscono in `dropped`, i datagrammi
+/// ancora incompleti a fine file in `incomplete`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct FragmentationStats {
+    pub fragments: u64,
+    pub fragmented_datagrams: u64,
+    pub reassembled: u64,
+    pub overlaps: u64,
+    pub timeouts: u64,
+    pub dropped: u64,
+    pub incomplete: u64,
+}
+
+impl FragmentationStats {
+    pub fn merge(&mut self, other: &FragmentationStats) {
+        self.fragments += other.fragments;
+        self.fragmented_datagrams += other.fragmented_datagrams;
+        self.reassembled += other.reassembled;
+        self.overlaps += other.overlaps;
+        self.timeouts += other.timeouts;
+        self.dropped += other.dropped;
+        self.incomplete += other.incomplete;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum DetectionKind {
+    VerticalScan,
+    HorizontalSweep,
+    UnansweredSyn,
+}
+
+/// Segnalazione di ricognizione: `count` è il numero di porte o host distinti
+/// (o di tentativi senza risposta), gli elenchi possono essere troncati.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Detection {
+    pub kind: DetectionKind,
+    pub source: String,
+    pub target_hosts: Vec<String>,
+    pub target_ports: Vec<u16>,
+    pub count: usize,
+    pub first_seen_us: u64,
+    pub last_seen_us: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct CaptureTiming {
+    pub first_packet_us: Option<u64>,
+    pub last_packet_us: Option<u64>,
+    pub duration_secs: f64,
+    pub avg_packets_per_sec: f64,
+    pub avg_bytes_per_sec: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct TimeBucket {
+    pub start_us: u64,
+    pub packets: u64,
+    pub bytes: u64,
+    pub by_protocol: HashMap<ProtocolKey, u32>,
+}
+
+/// Pacchetto grezzo letto da un file di cattura, prima della decodifica.
+pub struct CapturedPacket {
+    pub interface_index: usize,
+    pub timestamp_us: u64,
+    pub original_length: usize,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct CaptureInterface {
+    pub id: usize,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub link_type: i32,
+    pub snaplen: u32,
+    pub comments: Vec<String>,
+    pub received: Option<u64>,
+    pub dropped: Option<u64>,
+    pub os_dropped: Option<u64>,
+    pub filter_accepted: Option<u64>,
+    pub delivered: Option<u64>,
+}
+
+/// Metadati del file di cattura: per il pcapng provengono dai blocchi
+/// SHB, IDB e ISB, per il pcap classico solo dall'header globale.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default, Clone)]
+pub struct CaptureFileInfo {
+    pub format: String,
+    pub comments: Vec<String>,
+    pub hardware: Option<String>,
+    pub os: Option<String>,
+    pub application: Option<String>,
+    pub interfaces: Vec<CaptureInterface>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct NetworkStats {
     pub total_packets: usize,
     pub total_bytes_packet: u64,
+    pub total_bytes_captured: u64,
+    pub truncated_packets: u64,
+    pub truncated_bytes_lost: u64,
     pub by_protocol: HashMap<ProtocolKey, u32>,
-    pub top_10_ips: Vec<String>,
-    pub top_10_ports: Vec<u16>,
+    pub top_ips: TopRanking<String>,
+    pub top_ports: TopRanking<u16>,
+    pub top_sources: TopRanking<String>,
+    pub top_destinations: TopRanking<String>,
+    pub top_server_ports: TopRanking<String>,
+    pub top_conversations: TopRanking<String>,
+    pub top_subnets: TopRanking<String>,
+    pub by_zone: HashMap<String, TrafficCounter>,
+    /// Traffico fra zone: zona sorgente -> zona destinazione.
+    pub zone_matrix: HashMap<String, HashMap<String, TrafficCounter>>,
+    pub directions: DirectionStats,
+    pub by_country: HashMap<String, TrafficCounter>,
+    pub by_asn: HashMap<String, TrafficCounter>,
+    pub detections: Vec<Detection>,
+    pub tcp: TcpHealthStats,
+    pub fragmentation: FragmentationStats,
+    pub by_vlan: HashMap<String, TrafficCounter>,
+    pub tunnels: TunnelStats,
+    pub by_icmp_type: HashMap<String, u32>,
+    pub tls: TlsStats,
+    pub dns: DnsStats,
+    pub http: HttpStats,
+    pub top_flows: Vec<FlowRecord>,
+    pub capture: CaptureTiming,
+    pub time_series: Vec<TimeBucket>,
+    pub by_interface: HashMap<String, TrafficCounter>,
+    pub capture_file: Option<CaptureFileInfo>,
 }
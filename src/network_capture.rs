@@ -1,26 +1,177 @@
-use crate::pcap_helper::packet_mapper;
-use crate::model::PacketData;
-use pcap::{Capture};
+use crate::pcap_helper::{is_supported_link_type, packet_mapper};
+use crate::fragment_helper::FragmentReassembler;
+use crate::pcap_file_reader::{is_pcap_magic, PcapFileReader};
+use crate::pcapng_reader::{PcapngReader, PCAPNG_MAGIC};
+use crate::model::{CaptureFileInfo, CaptureInterface, CapturedPacket, DecodeOptions, PacketData};
+use flate2::read::MultiGzDecoder;
+use pcap::{Capture, Linktype};
 use serde::ser::StdError;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use xz2::read::XzDecoder;
 
-pub fn pcap_reader(file_path: &str) -> Result<Vec<PacketData>, Box<dyn StdError>> {
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Sorgente di pacchetti grezzi con il datalink di ciascuna interfaccia.
+pub trait CaptureReader {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, Box<dyn StdError>>;
+    fn link_type(&self, interface_index: usize) -> Option<u16>;
+    fn interface_label(&self, interface_index: usize) -> Option<String>;
+    fn into_info(self) -> CaptureFileInfo;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+/// Legge il file di cattura passando ogni pacchetto decodificato a
+/// `on_packet` appena letto, senza conservarli in memoria. I frammenti IP
+/// vengono riassemblati con `fragments` prima della decodifica del trasporto.
+pub fn pcap_reader(
+    file_path: &str,
+    options: &DecodeOptions,
+    fragments: &mut FragmentReassembler,
+    on_packet: &mut dyn FnMut(PacketData),
+) -> Result<CaptureFileInfo, Box<dyn StdError>> {
     print!("Lettura file: {} ", file_path);
 
+    let file = File::open(file_path)
+        .map_err(|e| format!("{} nell'apertura del file {}", e, file_path))?;
+    let mut file = BufReader::new(file);
+
+    // La compressione si riconosce dai magic bytes, indipendentemente dall'estensione.
+    let stream: Box<dyn Read> = match detect_compression(file.fill_buf()?) {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(file)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(file)?),
+        Some(Compression::Xz) => Box::new(XzDecoder::new_multi_decoder(file)),
+        None => {
+            if file.fill_buf()?.starts_with(&PCAPNG_MAGIC.to_le_bytes()) {
+                return stream_reader(PcapngReader::new(file), file_path, options, fragments, on_packet);
+            }
+            return libpcap_reader(file_path, options, fragments, on_packet);
+        }
+    };
+
+    let mut magic = [0u8; 4];
+    let mut stream = BufReader::new(stream);
+    stream.read_exact(&mut magic)
+        .map_err(|e| format!("{} nella decompressione del file {}", e, file_path))?;
+    let stream = Cursor::new(magic).chain(stream);
+
+    if u32::from_le_bytes(magic) == PCAPNG_MAGIC {
+        stream_reader(PcapngReader::new(stream), file_path, options, fragments, on_packet)
+    } else if is_pcap_magic(magic) {
+        stream_reader(PcapFileReader::new(stream)?, file_path, options, fragments, on_packet)
+    } else {
+        Err(format!("Il contenuto decompresso di {} non è un file PCAP o PCAPNG", file_path).into())
+    }
+}
+
+fn detect_compression(header: &[u8]) -> Option<Compression> {
+    if header.starts_with(GZIP_MAGIC) {
+        Some(Compression::Gzip)
+    } else if header.starts_with(ZSTD_MAGIC) {
+        Some(Compression::Zstd)
+    } else if header.starts_with(XZ_MAGIC) {
+        Some(Compression::Xz)
+    } else {
+        None
+    }
+}
+
+fn libpcap_reader(
+    file_path: &str,
+    options: &DecodeOptions,
+    fragments: &mut FragmentReassembler,
+    on_packet: &mut dyn FnMut(PacketData),
+) -> Result<CaptureFileInfo, Box<dyn StdError>> {
     let mut capture = match Capture::from_file(file_path) {
         Ok(cap) => cap,
         Err(e) => return Err(format!("{} nell'apertura del file PCAP {}",
           e, file_path,).into()),
     };
 
-    let mut data_packets: Vec<PacketData> = Vec::new();
+    let link_type = capture.get_datalink();
+    if !is_supported_link_type(link_type) {
+        return Err(unsupported_link_type(link_type, file_path).into());
+    }
+
+    let info = CaptureFileInfo {
+        format: "pcap".to_string(),
+        interfaces: vec![CaptureInterface {
+            link_type: link_type.0,
+            ..CaptureInterface::default()
+        }],
+        ..CaptureFileInfo::default()
+    };
 
     while let Ok(packet) = capture.next() {
+        let timestamp_us = packet.header.ts.tv_sec as u64 * 1_000_000 + packet.header.ts.tv_usec as u64;
+        fragments.advance_clock(timestamp_us);
+
+        if let Some(mut packet_data) = packet_mapper(link_type, packet.data, options, fragments) {
+            packet_data.timestamp_us = timestamp_us;
+            // La lunghezza originale sul filo va usata per i byte: quella catturata è limitata dallo snaplen.
+            packet_data.captured_length = packet.header.caplen as usize;
+            packet_data.packet_length = packet.header.len as usize;
+            on_packet(packet_data);
+        }
+
+    }
+    return Ok(info);
+
+}
+
+fn unsupported_link_type(link_type: Linktype, file_path: &str) -> String {
+    let link_name = link_type.get_name().unwrap_or_else(|_| "sconosciuto".to_string());
+    format!("Datalink {} ({}) non supportato nel file {}", link_name, link_type.0, file_path)
+}
 
-        if let Some(packet_data) = packet_mapper(packet.data) {
-            data_packets.push(packet_data);
+/// Decodifica i pacchetti di un lettore nativo usando il datalink della
+/// rispettiva interfaccia. Le interfacce con datalink non supportato vengono
+/// segnalate e i loro pacchetti ignorati.
+fn stream_reader<C: CaptureReader>(
+    mut reader: C,
+    file_path: &str,
+    options: &DecodeOptions,
+    fragments: &mut FragmentReassembler,
+    on_packet: &mut dyn FnMut(PacketData),
+) -> Result<CaptureFileInfo, Box<dyn StdError>> {
+    let mut reported_interfaces: Vec<usize> = Vec::new();
+
+    while let Some(packet) = reader.next_packet()? {
+        let link_type = match reader.link_type(packet.interface_index) {
+            Some(link_type) => Linktype(link_type as i32),
+            None => continue,
+        };
+        if !is_supported_link_type(link_type) {
+            if !reported_interfaces.contains(&packet.interface_index) {
+                eprintln!("Interfaccia {}: {}", packet.interface_index, unsupported_link_type(link_type, file_path));
+                reported_interfaces.push(packet.interface_index);
+            }
+            continue;
         }
 
+        fragments.advance_clock(packet.timestamp_us);
+        if let Some(mut packet_data) = packet_mapper(link_type, &packet.data, options, fragments) {
+            packet_data.timestamp_us = packet.timestamp_us;
+            packet_data.captured_length = packet.data.len();
+            packet_data.packet_length = packet.original_length;
+            packet_data.interface = reader.interface_label(packet.interface_index);
+            on_packet(packet_data);
+        }
     }
-    return Ok(data_packets);
 
-}
\ No newline at end of file
+    let info = reader.into_info();
+    if !info.interfaces.is_empty()
+        && info.interfaces.iter().all(|i| !is_supported_link_type(Linktype(i.link_type)))
+    {
+        return Err(format!("Nessuna interfaccia con datalink supportato nel file {}", file_path).into());
+    }
+    Ok(info)
+}
@@ -0,0 +1,120 @@
+use crate::model::{CaptureFileInfo, CaptureInterface, CapturedPacket};
+use crate::network_capture::CaptureReader;
+use std::error::Error;
+use std::io::{ErrorKind, Read};
+
+const MAGIC_MICROS: u32 = 0xA1B2C3D4;
+const MAGIC_NANOS: u32 = 0xA1B23C4D;
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+const MAX_RECORD_LEN: usize = 256 * 1024;
+
+/// Riconosce il magic number di un pcap classico in entrambi gli ordini di byte.
+pub fn is_pcap_magic(magic: [u8; 4]) -> bool {
+    [u32::from_le_bytes(magic), u32::from_be_bytes(magic)]
+        .iter()
+        .any(|m| *m == MAGIC_MICROS || *m == MAGIC_NANOS)
+}
+
+/// Lettore di pcap classico su un qualsiasi stream, usato quando il file
+/// non può essere aperto direttamente da libpcap (es. perché compresso).
+pub struct PcapFileReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanosecond: bool,
+    link_type: u16,
+    info: CaptureFileInfo,
+}
+
+impl<R: Read> PcapFileReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut header = [0u8; GLOBAL_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+
+        let magic = [header[0], header[1], header[2], header[3]];
+        let (big_endian, nanosecond) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (MAGIC_MICROS, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC_MICROS) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => return Err("Magic number PCAP non riconosciuto".into()),
+        };
+
+        let read_u32 = |bytes: &[u8]| {
+            let raw = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) }
+        };
+        let snaplen = read_u32(&header[16..20]);
+        // I 16 bit alti del campo network possono contenere FCS e flag.
+        let link_type = (read_u32(&header[20..24]) & 0xFFFF) as u16;
+
+        Ok(PcapFileReader {
+            reader,
+            big_endian,
+            nanosecond,
+            link_type,
+            info: CaptureFileInfo {
+                format: "pcap".to_string(),
+                interfaces: vec![CaptureInterface {
+                    link_type: link_type as i32,
+                    snaplen,
+                    ..CaptureInterface::default()
+                }],
+                ..CaptureFileInfo::default()
+            },
+        })
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32 {
+        let raw = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) }
+    }
+}
+
+impl<R: Read> CaptureReader for PcapFileReader<R> {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, Box<dyn Error>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let seconds = self.u32_from(&header[0..4]) as u64;
+        let fraction = self.u32_from(&header[4..8]) as u64;
+        let captured_length = self.u32_from(&header[8..12]) as usize;
+        let original_length = self.u32_from(&header[12..16]) as usize;
+        if captured_length > MAX_RECORD_LEN {
+            return Err(format!("Record PCAP con lunghezza non valida: {}", captured_length).into());
+        }
+
+        let mut data = vec![0u8; captured_length];
+        match self.reader.read_exact(&mut data) {
+            Ok(()) => {}
+            // Un ultimo record troncato viene scartato come fa libpcap.
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let micros = if self.nanosecond { fraction / 1_000 } else { fraction };
+
+        Ok(Some(CapturedPacket {
+            interface_index: 0,
+            timestamp_us: seconds * 1_000_000 + micros,
+            original_length,
+            data,
+        }))
+    }
+
+    fn link_type(&self, interface_index: usize) -> Option<u16> {
+        (interface_index == 0).then_some(self.link_type)
+    }
+
+    /// Il pcap classico ha una sola interfaccia: nessuna ripartizione.
+    fn interface_label(&self, _interface_index: usize) -> Option<String> {
+        None
+    }
+
+    fn into_info(self) -> CaptureFileInfo {
+        self.info
+    }
+}
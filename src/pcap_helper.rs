@@ -1,93 +1,646 @@
-use crate::model::{PacketData, InternetProtocol, TransportProtocol, ApplicationProtocol};
+use crate::model::{PacketData, InternetProtocol, TransportProtocol, ApplicationProtocol, PortRegistry, DecodeOptions, Tunnel, IcmpInfo, TcpInfo};
+use crate::tunnel_helper::{tunnel_payload, InnerFrame};
+use crate::tls_helper::{is_tls_record, parse_client_hello};
+use crate::dns_helper::parse_dns_message;
+use crate::http_helper::parse_http;
+use crate::fragment_helper::{FragmentKey, FragmentReassembler};
+use pcap::Linktype;
+use std::net::Ipv6Addr;
 
+const LINKTYPE_LINUX_SLL2: Linktype = Linktype(276);
+/// libpcap restituisce per i file pcap il valore DLT, non il LINKTYPE: il
+/// raw IP (LINKTYPE 101) diventa DLT_RAW, 12 ovunque tranne OpenBSD dove vale 14.
+const DLT_RAW: Linktype = Linktype(12);
+const DLT_RAW_OPENBSD: Linktype = Linktype(14);
 const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+const ETHERTYPE_QINQ_LEGACY: u16 = 0x9100;
+const VLAN_TAG_LEN: usize = 4;
+const ETHERNET_HEADER_LEN: usize = 14;
+const LINUX_SLL_HEADER_LEN: usize = 16;
+const LINUX_SLL2_HEADER_LEN: usize = 20;
+const NULL_HEADER_LEN: usize = 4;
+const PPP_PROTOCOL_IPV4: u16 = 0x0021;
+const PPP_PROTOCOL_IPV6: u16 = 0x0057;
 const IP_VERSION_IPV4: u8 = 4;
+const IP_VERSION_IPV6: u8 = 6;
+const IPV6_HEADER_LEN: usize = 40;
+const IPV6_FRAGMENT_HEADER_LEN: usize = 8;
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV4_FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
 const TRANSPORT_TCP: u8 = 0x06;
 const TRANSPORT_UDP: u8 = 0x11;
-const PORT_HTTP: u16 = 80;
-const PORT_HTTPS: u16 = 443;
-const PORT_DNS: u16 = 53;
+const TRANSPORT_ICMP: u8 = 0x01;
+const TRANSPORT_ICMPV6: u8 = 0x3A;
+const TCP_MIN_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const IPV6_EXT_HOP_BY_HOP: u8 = 0;
+const IPV6_EXT_ROUTING: u8 = 43;
+const IPV6_EXT_FRAGMENT: u8 = 44;
+const IPV6_EXT_AUTH: u8 = 51;
+const IPV6_EXT_DEST_OPTIONS: u8 = 60;
 
-pub fn packet_mapper(packet_data: &[u8]) -> Option<PacketData> {
+/// Indica se esiste un decoder di livello link per il datalink della cattura.
+pub fn is_supported_link_type(link_type: Linktype) -> bool {
+    matches!(
+        link_type,
+        Linktype::ETHERNET
+            | Linktype::LINUX_SLL
+            | LINKTYPE_LINUX_SLL2
+            | Linktype::RAW
+            | DLT_RAW
+            | DLT_RAW_OPENBSD
+            | Linktype::IPV4
+            | Linktype::IPV6
+            | Linktype::NULL
+            | Linktype::LOOP
+            | Linktype::PPP
+            | Linktype::PPP_HDLC
+    )
+}
+
+/// Stato della decodifica di un file: le opzioni condivise dai worker e il
+/// riassemblatore dei frammenti IP, che vive quanto la lettura del file.
+struct Decoder<'a> {
+    options: &'a DecodeOptions,
+    fragments: &'a mut FragmentReassembler,
+}
+
+pub fn packet_mapper(
+    link_type: Linktype,
+    packet_data: &[u8],
+    options: &DecodeOptions,
+    fragments: &mut FragmentReassembler,
+) -> Option<PacketData> {
     let packet_len: usize = packet_data.len();
+    let (ethertype, payload) = link_layer_decoder(link_type, packet_data)?;
+    let (ethertype, vlan_ids, ip_packet) = strip_vlan_tags(ethertype, payload)?;
 
-    if packet_len < 34 {
-        return None; 
+    let mut decoder = Decoder { options, fragments };
+    let mut packet = network_mapper(ethertype, ip_packet, packet_len, &mut decoder, 0)?;
+    packet.vlan_ids = vlan_ids;
+    Some(packet)
+}
+
+fn network_mapper(
+    ethertype: u16,
+    ip_packet: &[u8],
+    packet_len: usize,
+    decoder: &mut Decoder,
+    depth: u8,
+) -> Option<PacketData> {
+    match ethertype {
+        ETHERTYPE_IPV4 => ipv4_mapper(ip_packet, packet_len, decoder, depth),
+        ETHERTYPE_IPV6 => ipv6_mapper(ip_packet, packet_len, decoder, depth),
+        _ => None,
     }
+}
+
+/// Salta i tag 802.1Q/802.1ad (anche impilati in QinQ) e restituisce
+/// l'ethertype interno con i VLAN ID incontrati, dal più esterno.
+fn strip_vlan_tags(ethertype: u16, payload: &[u8]) -> Option<(u16, Vec<u16>, &[u8])> {
+    let mut ethertype = ethertype;
+    let mut payload = payload;
+    let mut vlan_ids = Vec::new();
 
-    let ethernet: u16 = u16::from_be_bytes([packet_data[12], packet_data[13]]);
-    if ethernet != ETHERTYPE_IPV4 {
-        return None; 
+    while matches!(ethertype, ETHERTYPE_VLAN | ETHERTYPE_QINQ | ETHERTYPE_QINQ_LEGACY) {
+        let tag = payload.get(..VLAN_TAG_LEN)?;
+        vlan_ids.push(u16::from_be_bytes([tag[0], tag[1]]) & 0x0FFF);
+        ethertype = u16::from_be_bytes([tag[2], tag[3]]);
+        payload = &payload[VLAN_TAG_LEN..];
     }
 
-    let ip_offset: usize = 14; 
-    let version = packet_data[ip_offset] >> 4;
-    if version != IP_VERSION_IPV4 {
-        return None;
+    Some((ethertype, vlan_ids, payload))
+}
+
+/// Rimuove l'header di livello link e restituisce l'ethertype del payload
+/// insieme ai byte a partire dall'header IP.
+fn link_layer_decoder(link_type: Linktype, packet_data: &[u8]) -> Option<(u16, &[u8])> {
+    match link_type {
+        Linktype::ETHERNET => {
+            let header = packet_data.get(..ETHERNET_HEADER_LEN)?;
+            let ethertype = u16::from_be_bytes([header[12], header[13]]);
+            Some((ethertype, &packet_data[ETHERNET_HEADER_LEN..]))
+        }
+        Linktype::LINUX_SLL => {
+            let header = packet_data.get(..LINUX_SLL_HEADER_LEN)?;
+            let ethertype = u16::from_be_bytes([header[14], header[15]]);
+            Some((ethertype, &packet_data[LINUX_SLL_HEADER_LEN..]))
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let header = packet_data.get(..LINUX_SLL2_HEADER_LEN)?;
+            let ethertype = u16::from_be_bytes([header[0], header[1]]);
+            Some((ethertype, &packet_data[LINUX_SLL2_HEADER_LEN..]))
+        }
+        Linktype::RAW | DLT_RAW | DLT_RAW_OPENBSD | Linktype::IPV4 | Linktype::IPV6 => raw_ip_decoder(packet_data),
+        Linktype::NULL | Linktype::LOOP => raw_ip_decoder(packet_data.get(NULL_HEADER_LEN..)?),
+        Linktype::PPP | Linktype::PPP_HDLC => ppp_decoder(packet_data),
+        _ => None,
     }
+}
+
+/// Per i datalink senza ethertype la famiglia si ricava dalla versione IP.
+fn raw_ip_decoder(ip_packet: &[u8]) -> Option<(u16, &[u8])> {
+    match *ip_packet.first()? >> 4 {
+        IP_VERSION_IPV4 => Some((ETHERTYPE_IPV4, ip_packet)),
+        IP_VERSION_IPV6 => Some((ETHERTYPE_IPV6, ip_packet)),
+        _ => None,
+    }
+}
+
+fn ppp_decoder(packet_data: &[u8]) -> Option<(u16, &[u8])> {
+    // Address e control (0xFF 0x03) sono opzionali nel framing HDLC-like.
+    let frame = match packet_data {
+        [0xFF, 0x03, rest @ ..] => rest,
+        _ => packet_data,
+    };
+    let header = frame.get(..2)?;
+    match u16::from_be_bytes([header[0], header[1]]) {
+        PPP_PROTOCOL_IPV4 => Some((ETHERTYPE_IPV4, &frame[2..])),
+        PPP_PROTOCOL_IPV6 => Some((ETHERTYPE_IPV6, &frame[2..])),
+        _ => None,
+    }
+}
 
-    let ihl: u8 = (packet_data[ip_offset] & 0x0F) * 4;
-    let transport_offset = ip_offset + ihl as usize;
+fn ipv4_mapper(
+    ip_packet: &[u8],
+    packet_len: usize,
+    decoder: &mut Decoder,
+    depth: u8,
+) -> Option<PacketData> {
+    if ip_packet.len() < 20 {
+        return None;
+    }
 
-    if packet_len < transport_offset + 4 {
-        return None; 
+    let version = ip_packet[0] >> 4;
+    if version != IP_VERSION_IPV4 {
+        return None;
     }
 
-    let protocol: u8 = packet_data[ip_offset + 9];
+    let ihl: u8 = (ip_packet[0] & 0x0F) * 4;
+    let transport_offset = ihl as usize;
+
+    let protocol: u8 = ip_packet[9];
     let source_ip: String = format!(
         "{}.{}.{}.{}",
-        packet_data[ip_offset + 12],
-        packet_data[ip_offset + 13],
-        packet_data[ip_offset + 14],
-        packet_data[ip_offset + 15]
+        ip_packet[12],
+        ip_packet[13],
+        ip_packet[14],
+        ip_packet[15]
     );
     let destination_ip = format!(
         "{}.{}.{}.{}",
-        packet_data[ip_offset + 16],
-        packet_data[ip_offset + 17],
-        packet_data[ip_offset + 18],
-        packet_data[ip_offset + 19]
+        ip_packet[16],
+        ip_packet[17],
+        ip_packet[18],
+        ip_packet[19]
     );
 
-    let source_port = u16::from_be_bytes([
-        packet_data[transport_offset],
-        packet_data[transport_offset + 1],
-    ]);
-    let destination_port = u16::from_be_bytes([
-        packet_data[transport_offset + 2],
-        packet_data[transport_offset + 3],
-    ]);
-
-    let (transport_layer, application_layer) = match protocol {
-        TRANSPORT_TCP => {
-            let application_protocol = match destination_port {
-                PORT_HTTP => Some(ApplicationProtocol::Http),
-                PORT_HTTPS => Some(ApplicationProtocol::Http),
-                PORT_DNS => Some(ApplicationProtocol::Dns),
-                _ => None,
-            };
-            (Some(TransportProtocol::Tcp), application_protocol)
+    // Con uno snaplen corto il pacchetto viene contato anche senza livello di trasporto.
+    let Some(transport_data) = ip_packet.get(transport_offset..) else {
+        return Some(PacketData::new(InternetProtocol::IPv4, source_ip, destination_ip, packet_len));
+    };
+    // Una total length nulla (offload TSO in cattura) non è affidabile: si usa quella catturata.
+    let total_length = u16::from_be_bytes([ip_packet[2], ip_packet[3]]) as usize;
+    let transport_len = if total_length == 0 {
+        transport_data.len()
+    } else {
+        total_length.saturating_sub(transport_offset)
+    };
+
+    // Un frammento si decodifica solo quando completa il datagramma; fino ad
+    // allora il pacchetto viene contato senza livello di trasporto.
+    let flags_offset = u16::from_be_bytes([ip_packet[6], ip_packet[7]]);
+    let more_fragments = flags_offset & IPV4_MORE_FRAGMENTS != 0;
+    let fragment_offset = (flags_offset & IPV4_FRAGMENT_OFFSET_MASK) as usize * 8;
+    let reassembled;
+    let (transport_data, transport_len) = if more_fragments || fragment_offset != 0 {
+        let key = FragmentKey {
+            source_ip: source_ip.clone(),
+            destination_ip: destination_ip.clone(),
+            id: u16::from_be_bytes([ip_packet[4], ip_packet[5]]) as u32,
+            protocol,
+            ipv6: false,
+        };
+        match reassemble(decoder, key, fragment_offset, more_fragments, transport_data.get(..transport_len)) {
+            Some(datagram) => {
+                reassembled = datagram;
+                (reassembled.as_slice(), reassembled.len())
+            }
+            None => return Some(PacketData::new(InternetProtocol::IPv4, source_ip, destination_ip, packet_len)),
         }
-        TRANSPORT_UDP => {
-            let application_protocol = match destination_port {
-                PORT_DNS => Some(ApplicationProtocol::Dns),
-                _ => None,
-            };
-            (Some(TransportProtocol::Udp), application_protocol)
+    } else {
+        (transport_data, transport_len)
+    };
+
+    if let Some(packet) = decapsulation_mapper(
+        protocol,
+        transport_data,
+        &source_ip,
+        &destination_ip,
+        packet_len,
+        decoder,
+        depth,
+    ) {
+        return Some(packet);
+    }
+
+    transport_mapper(
+        InternetProtocol::IPv4,
+        protocol,
+        TransportSegment { data: transport_data, declared_len: transport_len },
+        source_ip,
+        destination_ip,
+        packet_len,
+        &decoder.options.port_registry,
+    )
+}
+
+fn ipv6_mapper(
+    ip_packet: &[u8],
+    packet_len: usize,
+    decoder: &mut Decoder,
+    depth: u8,
+) -> Option<PacketData> {
+    if ip_packet.len() < IPV6_HEADER_LEN {
+        return None;
+    }
+
+    let version = ip_packet[0] >> 4;
+    if version != IP_VERSION_IPV6 {
+        return None;
+    }
+
+    let source_ip = ipv6_address(&ip_packet[8..24]);
+    let destination_ip = ipv6_address(&ip_packet[24..40]);
+    // Con uno snaplen corto la catena degli extension header può essere
+    // incompleta: il pacchetto viene contato senza livello di trasporto.
+    let Some((mut protocol, transport_offset)) = skip_ipv6_extensions(ip_packet, ip_packet[6], IPV6_HEADER_LEN) else {
+        return Some(PacketData::new(InternetProtocol::IPv6, source_ip, destination_ip, packet_len));
+    };
+    let Some(transport_data) = ip_packet.get(transport_offset..) else {
+        return Some(PacketData::new(InternetProtocol::IPv6, source_ip, destination_ip, packet_len));
+    };
+    // Payload length nulla: jumbogram o offload in cattura, vale la lunghezza catturata.
+    let payload_length = u16::from_be_bytes([ip_packet[4], ip_packet[5]]) as usize;
+    let transport_len = if payload_length == 0 {
+        transport_data.len()
+    } else {
+        (IPV6_HEADER_LEN + payload_length).saturating_sub(transport_offset)
+    };
+
+    // Dopo il riassemblaggio la parte frammentabile può iniziare con altri extension header.
+    let reassembled;
+    let (transport_data, transport_len) = if protocol == IPV6_EXT_FRAGMENT {
+        let Some(header) = transport_data.get(..IPV6_FRAGMENT_HEADER_LEN) else {
+            return Some(PacketData::new(InternetProtocol::IPv6, source_ip, destination_ip, packet_len));
+        };
+        let key = FragmentKey {
+            source_ip: source_ip.clone(),
+            destination_ip: destination_ip.clone(),
+            id: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            protocol: header[0],
+            ipv6: true,
+        };
+        let fragment_offset = (u16::from_be_bytes([header[2], header[3]]) & 0xFFF8) as usize;
+        let more_fragments = header[3] & 0x01 != 0;
+        let fragment = transport_data.get(IPV6_FRAGMENT_HEADER_LEN..transport_len);
+        match reassemble(decoder, key, fragment_offset, more_fragments, fragment) {
+            Some(datagram) => {
+                let (next_protocol, offset) = skip_ipv6_extensions(&datagram, header[0], 0)?;
+                protocol = next_protocol;
+                reassembled = datagram;
+                let data = reassembled.get(offset..)?;
+                (data, data.len())
+            }
+            None => return Some(PacketData::new(InternetProtocol::IPv6, source_ip, destination_ip, packet_len)),
         }
-        _ => return None,
+    } else {
+        (transport_data, transport_len)
     };
 
-    Some(PacketData {
-        internet_layer: InternetProtocol::IPv4,
-        transport_layer,
-        application_layer,
+    if let Some(packet) = decapsulation_mapper(
+        protocol,
+        transport_data,
+        &source_ip,
+        &destination_ip,
+        packet_len,
+        decoder,
+        depth,
+    ) {
+        return Some(packet);
+    }
+
+    transport_mapper(
+        InternetProtocol::IPv6,
+        protocol,
+        TransportSegment { data: transport_data, declared_len: transport_len },
         source_ip,
         destination_ip,
-        source_port,
-        destination_port,
-        packet_length: packet_len,
+        packet_len,
+        &decoder.options.port_registry,
+    )
+}
+
+fn ipv6_address(bytes: &[u8]) -> String {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(bytes);
+    Ipv6Addr::from(octets).to_string()
+}
+
+/// Segue la catena degli extension header IPv6 e restituisce il protocollo
+/// di trasporto con il suo offset. Si ferma sul fragment header, restituito
+/// come protocollo perché il resto va letto dal datagramma riassemblato.
+fn skip_ipv6_extensions(ip_packet: &[u8], first_header: u8, first_offset: usize) -> Option<(u8, usize)> {
+    let mut next_header = first_header;
+    let mut offset = first_offset;
+
+    loop {
+        match next_header {
+            IPV6_EXT_HOP_BY_HOP | IPV6_EXT_ROUTING | IPV6_EXT_DEST_OPTIONS => {
+                let header = ip_packet.get(offset..offset + 2)?;
+                next_header = header[0];
+                offset += (header[1] as usize + 1) * 8;
+            }
+            IPV6_EXT_AUTH => {
+                let header = ip_packet.get(offset..offset + 2)?;
+                next_header = header[0];
+                offset += (header[1] as usize + 2) * 4;
+            }
+            _ => return Some((next_header, offset)),
+        }
+    }
+}
+
+/// Passa un frammento al riassemblatore e restituisce il payload del
+/// datagramma quando è completo. Un frammento troncato dallo snaplen non
+/// viene trattenuto: il suo datagramma resterà incompleto.
+fn reassemble(
+    decoder: &mut Decoder,
+    key: FragmentKey,
+    fragment_offset: usize,
+    more_fragments: bool,
+    fragment: Option<&[u8]>,
+) -> Option<Vec<u8>> {
+    decoder.fragments.add(key, fragment_offset, more_fragments, fragment?)
+}
+
+/// Se il payload è un tunnel riconosciuto, decodifica ricorsivamente il
+/// pacchetto interno e registra il livello di incapsulamento attraversato.
+fn decapsulation_mapper(
+    protocol: u8,
+    payload: &[u8],
+    outer_source_ip: &str,
+    outer_destination_ip: &str,
+    packet_len: usize,
+    decoder: &mut Decoder,
+    depth: u8,
+) -> Option<PacketData> {
+    let decapsulation = &decoder.options.decapsulation;
+    if !decapsulation.enabled || depth >= decapsulation.max_depth {
+        return None;
+    }
+
+    let (encapsulation, inner) = tunnel_payload(protocol, payload, decapsulation)?;
+    let (ethertype, ip_packet) = match inner {
+        InnerFrame::Ip(ethertype, ip_packet) => (ethertype, ip_packet),
+        InnerFrame::Ethernet(frame) => {
+            let (ethertype, payload) = link_layer_decoder(Linktype::ETHERNET, frame)?;
+            let (ethertype, _, ip_packet) = strip_vlan_tags(ethertype, payload)?;
+            (ethertype, ip_packet)
+        }
+    };
+
+    let mut packet = network_mapper(ethertype, ip_packet, packet_len, decoder, depth + 1)?;
+    packet.tunnels.insert(0, Tunnel {
+        encapsulation,
+        outer_source_ip: outer_source_ip.to_string(),
+        outer_destination_ip: outer_destination_ip.to_string(),
+    });
+    Some(packet)
+}
+
+fn tcp_info(segment: &[u8], payload_len: usize) -> Option<TcpInfo> {
+    let header = segment.get(..TCP_MIN_HEADER_LEN)?;
+    Some(TcpInfo {
+        sequence: u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+        acknowledgment: u32::from_be_bytes([header[8], header[9], header[10], header[11]]),
+        window: u16::from_be_bytes([header[14], header[15]]),
+        payload_len: payload_len as u32,
     })
 }
 
+/// Dati del livello di trasporto con la lunghezza dichiarata nell'header IP,
+/// che può essere maggiore dei dati catturati (snaplen) o minore (padding Ethernet).
+struct TransportSegment<'a> {
+    data: &'a [u8],
+    declared_len: usize,
+}
+
+/// Completa il pacchetto con il livello di trasporto. Un header tagliato
+/// dallo snaplen lascia il pacchetto al livello IP, senza porte.
+fn transport_mapper(
+    internet_layer: InternetProtocol,
+    protocol: u8,
+    segment: TransportSegment,
+    source_ip: String,
+    destination_ip: String,
+    packet_len: usize,
+    port_registry: &PortRegistry,
+) -> Option<PacketData> {
+    let mut packet = PacketData::new(internet_layer, source_ip, destination_ip, packet_len);
+    let transport_len = segment.declared_len;
+    let transport_data = segment.data.get(..transport_len).unwrap_or(segment.data);
+
+    match protocol {
+        TRANSPORT_TCP | TRANSPORT_UDP => {
+            if transport_data.len() < 4 {
+                return Some(packet);
+            }
+            packet.source_port = u16::from_be_bytes([
+                transport_data[0],
+                transport_data[1],
+            ]);
+            packet.destination_port = u16::from_be_bytes([
+                transport_data[2],
+                transport_data[3],
+            ]);
+
+            let (transport_layer, header_len) = if protocol == TRANSPORT_TCP {
+                let data_offset = transport_data.get(12).map_or(0, |b| (b >> 4) as usize * 4);
+                let header_len = data_offset.max(TCP_MIN_HEADER_LEN);
+                packet.tcp_flags = transport_data.get(13).copied();
+                packet.tcp = tcp_info(transport_data, transport_len.saturating_sub(header_len));
+                (TransportProtocol::Tcp, header_len)
+            } else {
+                (TransportProtocol::Udp, UDP_HEADER_LEN)
+            };
+            packet.application_layer =
+                port_registry.classify(&transport_layer, packet.source_port, packet.destination_port);
+            packet.service_port =
+                port_registry.registered_port(&transport_layer, packet.source_port, packet.destination_port);
+            packet.transport_layer = Some(transport_layer);
+
+            let payload = transport_data.get(header_len..).unwrap_or(&[]);
+            application_mapper(&mut packet, payload);
+        }
+        TRANSPORT_ICMP | TRANSPORT_ICMPV6 => {
+            let Some(header) = transport_data.get(..2) else { return Some(packet) };
+            packet.transport_layer = Some(if protocol == TRANSPORT_ICMP {
+                TransportProtocol::Icmp
+            } else {
+                TransportProtocol::Icmpv6
+            });
+            packet.icmp = Some(IcmpInfo {
+                icmp_type: header[0],
+                code: header[1],
+            });
+        }
+        _ => packet.transport_layer = Some(TransportProtocol::Other(protocol)),
+    }
+
+    Some(packet)
+}
+
+/// Ispeziona il payload per riconoscere il protocollo applicativo anche
+/// quando la porta non corrisponde a quella registrata.
+fn application_mapper(packet: &mut PacketData, payload: &[u8]) {
+    let over_tcp = packet.transport_layer == Some(TransportProtocol::Tcp);
+
+    if over_tcp && is_tls_record(payload) {
+        packet.application_layer = Some(ApplicationProtocol::Tls);
+        packet.tls = parse_client_hello(payload);
+    } else if packet.application_layer == Some(ApplicationProtocol::Dns) {
+        packet.dns = parse_dns_message(payload, over_tcp);
+    } else if over_tcp && let Some(http) = parse_http(payload) {
+        packet.application_layer = Some(ApplicationProtocol::Http);
+        packet.http = Some(http);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ReassemblyConfig;
+
+    /// Datagramma UDP 192.0.2.1:5000 -> 198.51.100.1:6000 con 4 byte di payload.
+    const IPV4_UDP: [u8; 32] = [
+        0x45, 0x00, 0x00, 0x20, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00,
+        192, 0, 2, 1, 198, 51, 100, 1,
+        0x13, 0x88, 0x17, 0x70, 0x00, 0x0C, 0x00, 0x00,
+        0xDE, 0xAD, 0xBE, 0xEF,
+    ];
+
+    fn options() -> DecodeOptions {
+        DecodeOptions {
+            decapsulation: Default::default(),
+            port_registry: PortRegistry::default(),
+            reassembly: ReassemblyConfig::default(),
+        }
+    }
+
+    fn decode(link_type: Linktype, frame: &[u8]) -> Option<PacketData> {
+        let options = options();
+        let mut fragments = FragmentReassembler::new(&options.reassembly);
+        packet_mapper(link_type, frame, &options, &mut fragments)
+    }
 
+    fn with_header(header: &[u8], ip_packet: &[u8]) -> Vec<u8> {
+        [header, ip_packet].concat()
+    }
+
+    fn ipv6_udp() -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0, 0x00, 0x0C, TRANSPORT_UDP, 64];
+        packet.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        packet.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2).octets());
+        packet.extend_from_slice(&IPV4_UDP[20..]);
+        packet
+    }
+
+    fn assert_udp(packet: Option<PacketData>, source_ip: &str) {
+        let packet = packet.expect("pacchetto non decodificato");
+        assert_eq!(packet.source_ip, source_ip);
+        assert_eq!(packet.transport_layer, Some(TransportProtocol::Udp));
+        assert_eq!((packet.source_port, packet.destination_port), (5000, 6000));
+    }
+
+    #[test]
+    fn every_supported_link_type_is_decoded() {
+        let ethernet = [[0u8; 12].as_slice(), &[0x08, 0x00]].concat();
+        let mut linux_sll = [0u8; 16];
+        linux_sll[14..].copy_from_slice(&[0x08, 0x00]);
+        let mut linux_sll2 = [0u8; 20];
+        linux_sll2[..2].copy_from_slice(&[0x08, 0x00]);
+
+        let frames: Vec<(Linktype, Vec<u8>)> = vec![
+            (Linktype::ETHERNET, with_header(&ethernet, &IPV4_UDP)),
+            (Linktype::LINUX_SLL, with_header(&linux_sll, &IPV4_UDP)),
+            (LINKTYPE_LINUX_SLL2, with_header(&linux_sll2, &IPV4_UDP)),
+            (Linktype::RAW, IPV4_UDP.to_vec()),
+            (DLT_RAW, IPV4_UDP.to_vec()),
+            (DLT_RAW_OPENBSD, IPV4_UDP.to_vec()),
+            (Linktype::IPV4, IPV4_UDP.to_vec()),
+            (Linktype::NULL, with_header(&2u32.to_le_bytes(), &IPV4_UDP)),
+            (Linktype::LOOP, with_header(&2u32.to_be_bytes(), &IPV4_UDP)),
+            (Linktype::PPP, with_header(&[0x00, 0x21], &IPV4_UDP)),
+            (Linktype::PPP_HDLC, with_header(&[0xFF, 0x03, 0x00, 0x21], &IPV4_UDP)),
+        ];
+        for (link_type, frame) in frames {
+            assert!(is_supported_link_type(link_type), "{:?}", link_type);
+            assert_udp(decode(link_type, &frame), "192.0.2.1");
+        }
+    }
+
+    #[test]
+    fn raw_ip_link_types_detect_ipv6() {
+        for link_type in [Linktype::RAW, DLT_RAW, DLT_RAW_OPENBSD, Linktype::IPV6] {
+            assert_udp(decode(link_type, &ipv6_udp()), "2001:db8::1");
+        }
+        let ppp = with_header(&[0x00, 0x57], &ipv6_udp());
+        assert_udp(decode(Linktype::PPP, &ppp), "2001:db8::1");
+    }
+
+    #[test]
+    fn truncated_transport_headers_are_still_counted() {
+        let packet = decode(Linktype::RAW, &IPV4_UDP[..22]).unwrap();
+        assert_eq!(packet.source_ip, "192.0.2.1");
+        assert_eq!(packet.transport_layer, None);
+        assert_eq!((packet.source_port, packet.destination_port), (0, 0));
+
+        let mut icmp = IPV4_UDP[..21].to_vec();
+        icmp[9] = TRANSPORT_ICMP;
+        assert_eq!(decode(Linktype::RAW, &icmp).unwrap().transport_layer, None);
+
+        // Hop-by-hop header di 8 byte tagliato dopo i primi 2.
+        let mut ipv6 = ipv6_udp();
+        ipv6[6] = IPV6_EXT_HOP_BY_HOP;
+        ipv6.truncate(IPV6_HEADER_LEN + 2);
+        let packet = decode(Linktype::RAW, &ipv6).unwrap();
+        assert_eq!(packet.destination_ip, "2001:db8::2");
+        assert_eq!(packet.transport_layer, None);
+    }
+
+    #[test]
+    fn non_http_payload_keeps_registry_classification() {
+        let mut packet = PacketData::new(InternetProtocol::IPv4, "192.0.2.1".to_string(), "198.51.100.1".to_string(), 60);
+        packet.transport_layer = Some(TransportProtocol::Tcp);
+        packet.application_layer = Some(ApplicationProtocol::Ssh);
+        application_mapper(&mut packet, b"SSH-2.0-OpenSSH_9.6\r\n");
+        assert_eq!(packet.application_layer, Some(ApplicationProtocol::Ssh));
+        assert!(packet.http.is_none());
+
+        application_mapper(&mut packet, b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert_eq!(packet.application_layer, Some(ApplicationProtocol::Http));
+        assert_eq!(packet.http.and_then(|http| http.host).as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn unsupported_link_types_are_rejected() {
+        assert!(!is_supported_link_type(Linktype(147)));
+        assert!(decode(Linktype(147), &IPV4_UDP).is_none());
+        // Un frame PPP con protocollo diverso da IP non viene decodificato.
+        assert!(decode(Linktype::PPP, &with_header(&[0xC0, 0x21], &IPV4_UDP)).is_none());
+    }
+}
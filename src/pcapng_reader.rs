@@ -0,0 +1,506 @@
+use crate::model::{CaptureFileInfo, CaptureInterface, CapturedPacket};
+use crate::network_capture::CaptureReader;
+use std::error::Error;
+use std::io::{ErrorKind, Read};
+
+pub const PCAPNG_MAGIC: u32 = 0x0A0D0D0A;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_PACKET_OBSOLETE: u32 = 0x00000002;
+const BLOCK_SIMPLE_PACKET: u32 = 0x00000003;
+const BLOCK_INTERFACE_STATISTICS: u32 = 0x00000005;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+const OPTION_END: u16 = 0;
+const OPTION_COMMENT: u16 = 1;
+const SHB_HARDWARE: u16 = 2;
+const SHB_OS: u16 = 3;
+const SHB_USER_APPLICATION: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+const ISB_IFRECV: u16 = 4;
+const ISB_IFDROP: u16 = 5;
+const ISB_FILTERACCEPT: u16 = 6;
+const ISB_OSDROP: u16 = 7;
+const ISB_USRDELIV: u16 = 8;
+const MAX_BLOCK_LEN: usize = 64 * 1024 * 1024;
+
+/// Parametri dell'interfaccia necessari a interpretare i pacchetti.
+struct InterfaceState {
+    link_type: u16,
+    snaplen: u32,
+    units_per_second: u64,
+    offset_seconds: i64,
+}
+
+/// Lettore sequenziale di file pcapng. Gestisce più sezioni e più
+/// interfacce; gli indici di interfaccia restituiti sono globali al file
+/// e corrispondono alle voci di `info.interfaces`.
+pub struct PcapngReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    interfaces: Vec<InterfaceState>,
+    section_base: usize,
+    pub info: CaptureFileInfo,
+}
+
+impl<R: Read> PcapngReader<R> {
+    pub fn new(reader: R) -> Self {
+        PcapngReader {
+            reader,
+            big_endian: false,
+            interfaces: Vec::new(),
+            section_base: 0,
+            info: CaptureFileInfo {
+                format: "pcapng".to_string(),
+                ..CaptureFileInfo::default()
+            },
+        }
+    }
+
+    /// Link type dell'interfaccia con indice globale `interface_index`.
+    pub fn link_type(&self, interface_index: usize) -> Option<u16> {
+        self.interfaces.get(interface_index).map(|i| i.link_type)
+    }
+
+    /// Restituisce il pacchetto successivo. Un ultimo blocco troncato, come
+    /// in un file ancora in scrittura, equivale alla fine del file.
+    pub fn next_packet(&mut self) -> Result<Option<CapturedPacket>, Box<dyn Error>> {
+        loop {
+            let mut header = [0u8; 8];
+            if !self.read_or_eof(&mut header)? {
+                return Ok(None);
+            }
+
+            let raw_type = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            if raw_type == PCAPNG_MAGIC {
+                if !self.read_section_header(&header)? {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let block_type = self.u32_from(&header[0..4]);
+            let total_length = self.u32_from(&header[4..8]) as usize;
+            let Some(body) = self.read_block_body(total_length)? else { return Ok(None) };
+
+            let packet = match block_type {
+                BLOCK_INTERFACE_DESCRIPTION => {
+                    self.read_interface_description(&body)?;
+                    None
+                }
+                BLOCK_INTERFACE_STATISTICS => {
+                    self.read_interface_statistics(&body);
+                    None
+                }
+                BLOCK_ENHANCED_PACKET => self.read_enhanced_packet(&body),
+                BLOCK_PACKET_OBSOLETE => self.read_obsolete_packet(&body),
+                BLOCK_SIMPLE_PACKET => self.read_simple_packet(&body),
+                _ => None,
+            };
+
+            if packet.is_some() {
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Riempie `buffer`; restituisce `false` se il file finisce prima.
+    fn read_or_eof(&mut self, buffer: &mut [u8]) -> Result<bool, Box<dyn Error>> {
+        match self.reader.read_exact(buffer) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Legge il corpo del blocco; `None` se il file termina prima della sua fine.
+    fn read_block_body(&mut self, total_length: usize) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if total_length < 12 || !total_length.is_multiple_of(4) || total_length > MAX_BLOCK_LEN {
+            return Err(format!("Blocco pcapng con lunghezza non valida: {}", total_length).into());
+        }
+        let mut body = vec![0u8; total_length - 8];
+        if !self.read_or_eof(&mut body)? {
+            return Ok(None);
+        }
+        // Gli ultimi 4 byte ripetono la lunghezza del blocco.
+        body.truncate(total_length - 12);
+        Ok(Some(body))
+    }
+
+    /// Legge il Section Header Block; `false` se il file termina prima della sua fine.
+    fn read_section_header(&mut self, header: &[u8; 8]) -> Result<bool, Box<dyn Error>> {
+        let mut magic = [0u8; 4];
+        if !self.read_or_eof(&mut magic)? {
+            return Ok(false);
+        }
+        self.big_endian = match u32::from_le_bytes(magic) {
+            BYTE_ORDER_MAGIC => false,
+            _ if u32::from_be_bytes(magic) == BYTE_ORDER_MAGIC => true,
+            _ => return Err("Section Header Block pcapng con byte-order magic non valido".into()),
+        };
+
+        let total_length = self.u32_from(&header[4..8]) as usize;
+        let Some(body) = self.read_block_body(total_length.saturating_sub(4))? else { return Ok(false) };
+        // Versione (4 byte) e lunghezza della sezione (8 byte) precedono le opzioni.
+        for (code, value) in options(self.big_endian, body.get(12..).unwrap_or(&[])) {
+            let text = String::from_utf8_lossy(value).trim_end_matches('\0').to_string();
+            match code {
+                OPTION_COMMENT => self.info.comments.push(text),
+                SHB_HARDWARE => self.info.hardware = Some(text),
+                SHB_OS => self.info.os = Some(text),
+                SHB_USER_APPLICATION => self.info.application = Some(text),
+                _ => {}
+            }
+        }
+
+        // Gli Interface ID ripartono da zero in ogni sezione.
+        self.section_base = self.info.interfaces.len();
+        Ok(true)
+    }
+
+    fn read_interface_description(&mut self, body: &[u8]) -> Result<(), Box<dyn Error>> {
+        if body.len() < 8 {
+            return Err("Interface Description Block pcapng troncato".into());
+        }
+        let link_type = self.u16_from(&body[0..2]);
+        let snaplen = self.u32_from(&body[4..8]);
+        let index = self.info.interfaces.len();
+
+        let mut state = InterfaceState {
+            link_type,
+            snaplen,
+            units_per_second: 1_000_000,
+            offset_seconds: 0,
+        };
+        let mut interface = CaptureInterface {
+            id: index,
+            link_type: link_type as i32,
+            snaplen,
+            ..CaptureInterface::default()
+        };
+
+        for (code, value) in options(self.big_endian, &body[8..]) {
+            let text = || String::from_utf8_lossy(value).trim_end_matches('\0').to_string();
+            match code {
+                OPTION_COMMENT => interface.comments.push(text()),
+                IF_NAME => interface.name = Some(text()),
+                IF_DESCRIPTION => interface.description = Some(text()),
+                IF_TSRESOL => {
+                    if let Some(&resolution) = value.first() {
+                        let exponent = (resolution & 0x7F) as u32;
+                        let base: u64 = if resolution & 0x80 != 0 { 2 } else { 10 };
+                        state.units_per_second = base.checked_pow(exponent).unwrap_or(1_000_000);
+                    }
+                }
+                IF_TSOFFSET if value.len() >= 8 => {
+                    state.offset_seconds = self.u64_from(&value[..8]) as i64;
+                }
+                _ => {}
+            }
+        }
+
+        self.interfaces.push(state);
+        self.info.interfaces.push(interface);
+        Ok(())
+    }
+
+    fn read_interface_statistics(&mut self, body: &[u8]) {
+        if body.len() < 12 {
+            return;
+        }
+        let index = self.section_base + self.u32_from(&body[0..4]) as usize;
+        let counters: Vec<(u16, u64)> = options(self.big_endian, &body[12..])
+            .filter(|(_, value)| value.len() >= 8)
+            .map(|(code, value)| (code, self.u64_from(&value[..8])))
+            .collect();
+
+        if let Some(interface) = self.info.interfaces.get_mut(index) {
+            for (code, counter) in counters {
+                match code {
+                    ISB_IFRECV => interface.received = Some(counter),
+                    ISB_IFDROP => interface.dropped = Some(counter),
+                    ISB_FILTERACCEPT => interface.filter_accepted = Some(counter),
+                    ISB_OSDROP => interface.os_dropped = Some(counter),
+                    ISB_USRDELIV => interface.delivered = Some(counter),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn read_enhanced_packet(&self, body: &[u8]) -> Option<CapturedPacket> {
+        let header = body.get(..20)?;
+        let interface_id = self.u32_from(&header[0..4]) as usize;
+        let timestamp = (self.u32_from(&header[4..8]) as u64) << 32 | self.u32_from(&header[8..12]) as u64;
+        let captured_length = self.u32_from(&header[12..16]) as usize;
+        let original_length = self.u32_from(&header[16..20]) as usize;
+        let data = body.get(20..20 + captured_length)?;
+        self.build_packet(interface_id, timestamp, original_length, data)
+    }
+
+    fn read_obsolete_packet(&self, body: &[u8]) -> Option<CapturedPacket> {
+        let header = body.get(..20)?;
+        let interface_id = self.u16_from(&header[0..2]) as usize;
+        let timestamp = (self.u32_from(&header[4..8]) as u64) << 32 | self.u32_from(&header[8..12]) as u64;
+        let captured_length = self.u32_from(&header[12..16]) as usize;
+        let original_length = self.u32_from(&header[16..20]) as usize;
+        let data = body.get(20..20 + captured_length)?;
+        self.build_packet(interface_id, timestamp, original_length, data)
+    }
+
+    /// Il Simple Packet Block non ha timestamp e si riferisce sempre alla
+    /// prima interfaccia della sezione.
+    fn read_simple_packet(&self, body: &[u8]) -> Option<CapturedPacket> {
+        let original_length = self.u32_from(body.get(..4)?) as usize;
+        let snaplen = self.interfaces.get(self.section_base)?.snaplen as usize;
+        let captured_length = if snaplen > 0 { original_length.min(snaplen) } else { original_length };
+        let data = body.get(4..4 + captured_length.min(body.len() - 4))?;
+        self.build_packet(0, 0, original_length, data)
+    }
+
+    fn build_packet(
+        &self,
+        interface_id: usize,
+        timestamp: u64,
+        original_length: usize,
+        data: &[u8],
+    ) -> Option<CapturedPacket> {
+        let interface_index = self.section_base + interface_id;
+        let interface = self.interfaces.get(interface_index)?;
+        let micros = timestamp as u128 * 1_000_000 / interface.units_per_second.max(1) as u128;
+        let timestamp_us = (micros as i128 + interface.offset_seconds as i128 * 1_000_000).max(0) as u64;
+
+        Some(CapturedPacket {
+            interface_index,
+            timestamp_us,
+            original_length,
+            data: data.to_vec(),
+        })
+    }
+
+    fn u16_from(&self, bytes: &[u8]) -> u16 {
+        let raw = [bytes[0], bytes[1]];
+        if self.big_endian { u16::from_be_bytes(raw) } else { u16::from_le_bytes(raw) }
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32 {
+        let raw = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian { u32::from_be_bytes(raw) } else { u32::from_le_bytes(raw) }
+    }
+
+    fn u64_from(&self, bytes: &[u8]) -> u64 {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(&bytes[..8]);
+        if self.big_endian { u64::from_be_bytes(raw) } else { u64::from_le_bytes(raw) }
+    }
+}
+
+impl<R: Read> CaptureReader for PcapngReader<R> {
+    fn next_packet(&mut self) -> Result<Option<CapturedPacket>, Box<dyn Error>> {
+        PcapngReader::next_packet(self)
+    }
+
+    fn link_type(&self, interface_index: usize) -> Option<u16> {
+        PcapngReader::link_type(self, interface_index)
+    }
+
+    fn interface_label(&self, interface_index: usize) -> Option<String> {
+        let interface = self.info.interfaces.get(interface_index)?;
+        Some(interface.name.clone().unwrap_or_else(|| format!("if{}", interface.id)))
+    }
+
+    fn into_info(self) -> CaptureFileInfo {
+        self.info
+    }
+}
+
+/// Itera sulle opzioni (codice, valore) di un blocco fino a opt_endofopt.
+fn options(big_endian: bool, data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let read_u16 = move |b: &[u8]| if big_endian {
+        u16::from_be_bytes([b[0], b[1]])
+    } else {
+        u16::from_le_bytes([b[0], b[1]])
+    };
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        let header = data.get(offset..offset + 4)?;
+        let code = read_u16(&header[0..2]);
+        let length = read_u16(&header[2..4]) as usize;
+        if code == OPTION_END {
+            return None;
+        }
+        let value = data.get(offset + 4..offset + 4 + length)?;
+        // I valori delle opzioni sono allineati a 32 bit.
+        offset += 4 + length.div_ceil(4) * 4;
+        Some((code, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_bytes(value: u16, big_endian: bool) -> [u8; 2] {
+        if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    fn u32_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+        if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    fn option(code: u16, value: &[u8], big_endian: bool) -> Vec<u8> {
+        let mut bytes = u16_bytes(code, big_endian).to_vec();
+        bytes.extend(u16_bytes(value.len() as u16, big_endian));
+        bytes.extend(value);
+        bytes.resize(4 + value.len().div_ceil(4) * 4, 0);
+        bytes
+    }
+
+    fn block(block_type: u32, body: &[u8], big_endian: bool) -> Vec<u8> {
+        let total_length = (12 + body.len().div_ceil(4) * 4) as u32;
+        let mut bytes = u32_bytes(block_type, big_endian).to_vec();
+        bytes.extend(u32_bytes(total_length, big_endian));
+        bytes.extend(body);
+        bytes.resize(total_length as usize - 4, 0);
+        bytes.extend(u32_bytes(total_length, big_endian));
+        bytes
+    }
+
+    fn section_header(options: &[u8], big_endian: bool) -> Vec<u8> {
+        let mut body = u32_bytes(BYTE_ORDER_MAGIC, big_endian).to_vec();
+        body.extend(u16_bytes(1, big_endian));
+        body.extend(u16_bytes(0, big_endian));
+        body.extend([0xFF; 8]);
+        body.extend(options);
+        block(PCAPNG_MAGIC, &body, big_endian)
+    }
+
+    fn interface_description(link_type: u16, snaplen: u32, options: &[u8], big_endian: bool) -> Vec<u8> {
+        let mut body = u16_bytes(link_type, big_endian).to_vec();
+        body.extend([0, 0]);
+        body.extend(u32_bytes(snaplen, big_endian));
+        body.extend(options);
+        block(BLOCK_INTERFACE_DESCRIPTION, &body, big_endian)
+    }
+
+    fn enhanced_packet(interface_id: u32, timestamp: u64, data: &[u8], original_length: u32, big_endian: bool) -> Vec<u8> {
+        let mut body = u32_bytes(interface_id, big_endian).to_vec();
+        body.extend(u32_bytes((timestamp >> 32) as u32, big_endian));
+        body.extend(u32_bytes(timestamp as u32, big_endian));
+        body.extend(u32_bytes(data.len() as u32, big_endian));
+        body.extend(u32_bytes(original_length, big_endian));
+        body.extend(data);
+        block(BLOCK_ENHANCED_PACKET, &body, big_endian)
+    }
+
+    #[test]
+    fn reads_sections_interfaces_and_packets() {
+        let mut file = section_header(&option(SHB_OS, b"Linux", false), false);
+        let mut if_options = option(IF_NAME, b"eth0", false);
+        if_options.extend(option(IF_TSRESOL, &[9], false));
+        file.extend(interface_description(1, 65535, &if_options, false));
+        file.extend(enhanced_packet(0, 1_500_000_000, &[0xAA; 5], 60, false));
+
+        let mut statistics = u32_bytes(0, false).to_vec();
+        statistics.extend([0; 8]);
+        statistics.extend(option(ISB_IFDROP, &7u64.to_le_bytes(), false));
+        file.extend(block(BLOCK_INTERFACE_STATISTICS, &statistics, false));
+
+        // Seconda sezione big-endian: l'interfaccia 0 diventa la globale 1.
+        file.extend(section_header(&[], true));
+        file.extend(interface_description(101, 0, &[], true));
+        file.extend(enhanced_packet(0, 2_000_000, &[0xBB; 3], 3, true));
+
+        let mut reader = PcapngReader::new(file.as_slice());
+        let first = reader.next_packet().unwrap().unwrap();
+        assert_eq!(first.interface_index, 0);
+        assert_eq!(first.timestamp_us, 1_500_000);
+        assert_eq!(first.original_length, 60);
+        assert_eq!(first.data, vec![0xAA; 5]);
+
+        let second = reader.next_packet().unwrap().unwrap();
+        assert_eq!(second.interface_index, 1);
+        assert_eq!(second.timestamp_us, 2_000_000);
+        assert_eq!(second.data, vec![0xBB; 3]);
+        assert!(reader.next_packet().unwrap().is_none());
+
+        assert_eq!(reader.link_type(1), Some(101));
+        assert_eq!(reader.interface_label(0).as_deref(), Some("eth0"));
+        assert_eq!(reader.interface_label(1).as_deref(), Some("if1"));
+        let info = reader.into_info();
+        assert_eq!(info.os.as_deref(), Some("Linux"));
+        assert_eq!(info.interfaces[0].dropped, Some(7));
+    }
+
+    #[test]
+    fn simple_packet_is_limited_by_snaplen() {
+        let mut file = section_header(&[], false);
+        file.extend(interface_description(1, 4, &[], false));
+        let mut body = u32_bytes(10, false).to_vec();
+        body.extend([0xCC; 10]);
+        file.extend(block(BLOCK_SIMPLE_PACKET, &body, false));
+
+        let packet = PcapngReader::new(file.as_slice()).next_packet().unwrap().unwrap();
+        assert_eq!(packet.original_length, 10);
+        assert_eq!(packet.data, vec![0xCC; 4]);
+    }
+
+    #[test]
+    fn skips_packets_with_inconsistent_lengths() {
+        let mut file = section_header(&[], false);
+        file.extend(interface_description(1, 0, &[], false));
+        let mut packet = enhanced_packet(0, 0, &[0xDD; 4], 4, false);
+        packet[20..24].copy_from_slice(&1000u32.to_le_bytes());
+        file.extend(packet);
+        // Pacchetto riferito a un'interfaccia inesistente.
+        file.extend(enhanced_packet(3, 0, &[0xDD; 4], 4, false));
+
+        assert!(PcapngReader::new(file.as_slice()).next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_block_lengths() {
+        let mut file = section_header(&[], false);
+        file.extend(u32_bytes(BLOCK_ENHANCED_PACKET, false));
+        file.extend(u32_bytes(13, false));
+        file.extend([0; 8]);
+
+        assert!(PcapngReader::new(file.as_slice()).next_packet().is_err());
+    }
+
+    #[test]
+    fn truncated_last_block_ends_the_file() {
+        let mut file = section_header(&[], false);
+        file.extend(interface_description(1, 0, &[], false));
+        file.extend(enhanced_packet(0, 0, &[0xEE; 4], 4, false));
+        let complete = file.len();
+        file.extend(enhanced_packet(0, 1, &[0xEE; 40], 40, false));
+
+        // Corpo del blocco interrotto, poi header interrotto.
+        for cut in [file.len() - 10, complete + 5] {
+            let mut reader = PcapngReader::new(&file[..cut]);
+            assert!(reader.next_packet().unwrap().is_some());
+            assert!(reader.next_packet().unwrap().is_none());
+        }
+
+        // Anche una sezione interrotta dopo l'header del blocco.
+        let section = section_header(&[], false);
+        assert!(PcapngReader::new(&section[..10]).next_packet().unwrap().is_none());
+        assert!(PcapngReader::new(&section[..14]).next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn options_stop_at_end_or_truncation() {
+        let mut data = option(IF_NAME, b"abc", false);
+        data.extend(option(OPTION_END, &[], false));
+        data.extend(option(IF_DESCRIPTION, b"ignored", false));
+        let parsed: Vec<(u16, &[u8])> = options(false, &data).collect();
+        assert_eq!(parsed, vec![(IF_NAME, b"abc".as_slice())]);
+
+        let truncated = &option(IF_NAME, b"abcdef", false)[..6];
+        assert_eq!(options(false, truncated).count(), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::model::{ApplicationProtocol, ClassificationConfig, PortRegistry, TransportProtocol};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+const DEFAULT_TCP_PORTS: &[(u16, ApplicationProtocol)] = &[
+    (20, ApplicationProtocol::Ftp),
+    (21, ApplicationProtocol::Ftp),
+    (22, ApplicationProtocol::Ssh),
+    (23, ApplicationProtocol::Telnet),
+    (25, ApplicationProtocol::Smtp),
+    (53, ApplicationProtocol::Dns),
+    (80, ApplicationProtocol::Http),
+    (88, ApplicationProtocol::Kerberos),
+    (110, ApplicationProtocol::Pop3),
+    (139, ApplicationProtocol::NetBios),
+    (143, ApplicationProtocol::Imap),
+    (179, ApplicationProtocol::Bgp),
+    (389, ApplicationProtocol::Ldap),
+    (443, ApplicationProtocol::Tls),
+    (445, ApplicationProtocol::Smb),
+    (465, ApplicationProtocol::Smtp),
+    (587, ApplicationProtocol::Smtp),
+    (636, ApplicationProtocol::Ldap),
+    (993, ApplicationProtocol::Imap),
+    (995, ApplicationProtocol::Pop3),
+    (3306, ApplicationProtocol::Mysql),
+    (3389, ApplicationProtocol::Rdp),
+    (5060, ApplicationProtocol::Sip),
+    (5432, ApplicationProtocol::Postgres),
+    (8080, ApplicationProtocol::Http),
+];
+
+const DEFAULT_UDP_PORTS: &[(u16, ApplicationProtocol)] = &[
+    (53, ApplicationProtocol::Dns),
+    (67, ApplicationProtocol::Dhcp),
+    (68, ApplicationProtocol::Dhcp),
+    (88, ApplicationProtocol::Kerberos),
+    (123, ApplicationProtocol::Ntp),
+    (137, ApplicationProtocol::NetBios),
+    (138, ApplicationProtocol::NetBios),
+    (161, ApplicationProtocol::Snmp),
+    (162, ApplicationProtocol::Snmp),
+    (389, ApplicationProtocol::Ldap),
+    (514, ApplicationProtocol::Syslog),
+    (546, ApplicationProtocol::Dhcp),
+    (547, ApplicationProtocol::Dhcp),
+    (3389, ApplicationProtocol::Rdp),
+    (5060, ApplicationProtocol::Sip),
+];
+
+/// Costruisce il registro partendo dai default, poi il file in formato
+/// /etc/services e infine le porte esplicite della configurazione.
+pub fn build_registry(config: &ClassificationConfig) -> Result<PortRegistry, Box<dyn Error>> {
+    let mut registry = PortRegistry::default();
+    registry.tcp.extend(DEFAULT_TCP_PORTS.iter().cloned());
+    registry.udp.extend(DEFAULT_UDP_PORTS.iter().cloned());
+
+    if let Some(services_file) = &config.services_file {
+        let content = fs::read_to_string(services_file)
+            .map_err(|e| format!("{} nella lettura del file servizi {}", e, services_file))?;
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            if let (Some(name), Some(port_spec)) = (fields.next(), fields.next()) {
+                // Righe malformate o con protocolli diversi da tcp/udp vengono ignorate.
+                let _ = register_port(&mut registry, port_spec, name);
+            }
+        }
+    }
+
+    for (port_spec, name) in &config.ports {
+        register_port(&mut registry, port_spec, name)
+            .map_err(|e| format!("Porta {} non valida in configurazione: {}", port_spec, e))?;
+    }
+
+    Ok(registry)
+}
+
+fn register_port(registry: &mut PortRegistry, port_spec: &str, name: &str) -> Result<(), String> {
+    let (port, transport) = port_spec
+        .split_once('/')
+        .ok_or_else(|| "formato atteso porta/protocollo".to_string())?;
+    let port: u16 = port.parse().map_err(|_| format!("porta {} non numerica", port))?;
+    let protocol = protocol_from_name(name);
+
+    match transport.to_ascii_lowercase().as_str() {
+        "tcp" => registry.tcp.insert(port, protocol),
+        "udp" => registry.udp.insert(port, protocol),
+        other => return Err(format!("protocollo di trasporto {} non supportato", other)),
+    };
+    Ok(())
+}
+
+/// Converte un nome di servizio (anche nella forma di /etc/services) nella
+/// variante corrispondente, ricadendo su `Other` per i nomi sconosciuti.
+pub fn protocol_from_name(name: &str) -> ApplicationProtocol {
+    match name.to_ascii_lowercase().as_str() {
+        "dns" | "domain" => ApplicationProtocol::Dns,
+        "http" | "www" | "www-http" | "http-alt" => ApplicationProtocol::Http,
+        "tls" | "https" => ApplicationProtocol::Tls,
+        "ftp" | "ftp-data" | "ftps" | "ftps-data" => ApplicationProtocol::Ftp,
+        "ssh" => ApplicationProtocol::Ssh,
+        "telnet" => ApplicationProtocol::Telnet,
+        "smtp" | "submission" | "submissions" | "smtps" => ApplicationProtocol::Smtp,
+        "pop3" | "pop3s" => ApplicationProtocol::Pop3,
+        "imap" | "imap2" | "imap3" | "imaps" => ApplicationProtocol::Imap,
+        "ntp" => ApplicationProtocol::Ntp,
+        "dhcp" | "bootps" | "bootpc" | "dhcpv6-client" | "dhcpv6-server" => ApplicationProtocol::Dhcp,
+        "snmp" | "snmp-trap" => ApplicationProtocol::Snmp,
+        "ldap" | "ldaps" => ApplicationProtocol::Ldap,
+        "kerberos" | "kerberos-sec" | "kerberos5" => ApplicationProtocol::Kerberos,
+        "rdp" | "ms-wbt-server" => ApplicationProtocol::Rdp,
+        "smb" | "microsoft-ds" => ApplicationProtocol::Smb,
+        "netbios" | "netbios-ns" | "netbios-dgm" | "netbios-ssn" => ApplicationProtocol::NetBios,
+        "syslog" => ApplicationProtocol::Syslog,
+        "sip" | "sip-tls" => ApplicationProtocol::Sip,
+        "bgp" => ApplicationProtocol::Bgp,
+        "mysql" => ApplicationProtocol::Mysql,
+        "postgres" | "postgresql" => ApplicationProtocol::Postgres,
+        _ => ApplicationProtocol::Other(name.to_string()),
+    }
+}
+
+impl PortRegistry {
+    /// Considera entrambe le porte, provando prima la più bassa: il lato
+    /// well-known di una connessione è quasi sempre quello con porta minore.
+    pub fn classify(
+        &self,
+        transport: &TransportProtocol,
+        source_port: u16,
+        destination_port: u16,
+    ) -> Option<ApplicationProtocol> {
+        let port = self.registered_port(transport, source_port, destination_port)?;
+        self.table(transport)?.get(&port).cloned()
+    }
+
+    /// La porta registrata della connessione, scelta come in `classify`.
+    pub fn registered_port(
+        &self,
+        transport: &TransportProtocol,
+        source_port: u16,
+        destination_port: u16,
+    ) -> Option<u16> {
+        let table = self.table(transport)?;
+        let (low, high) = if source_port <= destination_port {
+            (source_port, destination_port)
+        } else {
+            (destination_port, source_port)
+        };
+
+        [low, high].into_iter().find(|port| table.contains_key(port))
+    }
+
+    fn table(&self, transport: &TransportProtocol) -> Option<&HashMap<u16, ApplicationProtocol>> {
+        match transport {
+            TransportProtocol::Tcp => Some(&self.tcp),
+            TransportProtocol::Udp => Some(&self.udp),
+            _ => None,
+        }
+    }
+}
@@ -1,10 +1,14 @@
-use crate::model::{Config, PacketData};
+use crate::model::{Config, DecodeOptions, StatsConfig};
+use crate::dns_helper::{load_passive_dns, SharedPassiveDns};
+use crate::fragment_helper::FragmentReassembler;
+use crate::geoip_helper::GeoIpDatabase;
+use crate::stat_helper::StatsAccumulator;
 use crate::thread::factory::{create_thread, ThreadHandle, ThreadType};
 use crate::util;
 use std::error::Error;
 use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 pub fn load_config() -> Config {
     const CONFIG_PATH: &str = "properties.json";
@@ -20,21 +24,35 @@ pub fn load_config() -> Config {
 pub fn monitor_network(config: Config) -> Result<(), Box<dyn Error>> {
     let (watcher_tx, watcher_rx) = channel::<PathBuf>();
 
-    let (packet_tx, packet_rx) = channel::<Vec<PacketData>>();
-    let packet_tx = Arc::new(packet_tx);
+    let (summary_tx, summary_rx) = channel::<StatsAccumulator>();
+    let summary_tx = Arc::new(summary_tx);
 
-    let worker_fn: Arc<dyn Fn(String, String) -> Result<Vec<PacketData>, Box<dyn Error>> + Send + Sync> =
-        Arc::new(process_local_pcap);
+    let export_flows = config.export_flows;
+    let settings = config.stats.clone();
+    let options = DecodeOptions {
+        decapsulation: config.decapsulation.clone(),
+        port_registry: crate::port_registry::build_registry(&config.classification)?,
+        reassembly: config.reassembly.clone(),
+    };
+    let geoip = crate::geoip_helper::load_geoip(&config.geoip)?.map(Arc::new);
+    let worker_geoip = geoip.clone();
+    let passive_dns_path = format!("{}/passive_dns.json", config.output_dir);
+    let shared_dns: SharedPassiveDns = Arc::new(RwLock::new(load_passive_dns(&passive_dns_path)));
+    let worker_dns = shared_dns.clone();
+    let worker_fn: Arc<dyn Fn(String, String) -> Result<StatsAccumulator, Box<dyn Error>> + Send + Sync> =
+        Arc::new(move |input, output| {
+            process_local_pcap(input, output, &options, &settings, worker_geoip.clone(), worker_dns.clone(), export_flows)
+        });
 
     let (worker_handles, job_senders) =
-        generate_workers_with_assignment(config.parallelism as usize, packet_tx.clone(), worker_fn);
+        generate_workers_with_assignment(config.parallelism as usize, summary_tx.clone(), worker_fn);
 
     let watcher_handle = create_watcher(&config.watch_dir, watcher_tx);
-    let aggregator_handle = create_aggregator(&config.output_dir, packet_rx);
+    let aggregator_handle = create_aggregator(&config.output_dir, summary_rx, &config.stats, geoip, shared_dns);
 
     crate::job_dispatcher::dispatch_jobs(watcher_rx, job_senders, &config.output_dir);
 
-    drop(packet_tx);
+    drop(summary_tx);
     wait_for_workers(worker_handles);
     aggregator_handle.join();
     watcher_handle.join();
@@ -50,25 +68,36 @@ fn create_watcher(watch_dir: &str, sender: Sender<PathBuf>) -> ThreadHandle {
     })
 }
 
-fn create_aggregator(output_dir: &str, packet_rx: Receiver<Vec<PacketData>>) -> ThreadHandle {
+fn create_aggregator(
+    output_dir: &str,
+    summary_rx: Receiver<StatsAccumulator>,
+    settings: &StatsConfig,
+    geoip: Option<Arc<GeoIpDatabase>>,
+    passive_dns: SharedPassiveDns,
+) -> ThreadHandle {
     let stats_path = format!("{}/total_stats.json", output_dir);
-    let save_stats = move |state: &Vec<PacketData>| -> Result<(), Box<dyn Error>> {
-        let stats = crate::stat_helper::generate_stats(state);
-        crate::util::update_file(&stats_path, &stats)?;
+    let passive_dns_path = format!("{}/passive_dns.json", output_dir);
+    let saved_dns = passive_dns.clone();
+    let save_stats = move |state: &StatsAccumulator| -> Result<(), Box<dyn Error>> {
+        crate::util::update_file(&stats_path, &state.to_stats())?;
+        crate::util::update_file(&passive_dns_path, &*saved_dns.read().unwrap())?;
         Ok(())
     };
 
     create_thread(ThreadType::Aggregator {
         name: "aggregator".to_string(),
-        packet_rx,
+        summary_rx,
+        settings: settings.clone(),
+        geoip,
+        passive_dns,
         save_stats: Box::new(save_stats),
     })
 }
 
 fn generate_workers_with_assignment(
     count: usize,
-    packet_tx: Arc<Sender<Vec<PacketData>>>,
-    worker_fn: Arc<dyn Fn(String, String) -> Result<Vec<PacketData>, Box<dyn Error>> + Send + Sync>,
+    summary_tx: Arc<Sender<StatsAccumulator>>,
+    worker_fn: Arc<dyn Fn(String, String) -> Result<StatsAccumulator, Box<dyn Error>> + Send + Sync>,
 ) -> (Vec<ThreadHandle>, Vec<Sender<(String, String)>>) {
     let mut handles = Vec::with_capacity(count);
     let mut job_senders = Vec::with_capacity(count);
@@ -80,7 +109,7 @@ fn generate_workers_with_assignment(
         let worker = create_thread(ThreadType::Worker {
             name,
             job_rx: Arc::new(std::sync::Mutex::new(job_rx)),
-            packet_tx: Arc::clone(&packet_tx),
+            summary_tx: Arc::clone(&summary_tx),
             worker_fn: Arc::clone(&worker_fn),
         });
 
@@ -99,9 +128,38 @@ fn wait_for_workers(workers: Vec<ThreadHandle>) {
     }
 }
 
-fn process_local_pcap(input: String, output: String) -> Result<Vec<PacketData>, Box<dyn Error>> {
-    let packets = crate::network_capture::pcap_reader(&input)?;
-    let stats = crate::stat_helper::generate_stats(&packets);
+fn process_local_pcap(
+    input: String,
+    output: String,
+    options: &DecodeOptions,
+    settings: &StatsConfig,
+    geoip: Option<Arc<GeoIpDatabase>>,
+    passive_dns: SharedPassiveDns,
+    export_flows: bool,
+) -> Result<StatsAccumulator, Box<dyn Error>> {
+    let mut accumulator = StatsAccumulator::new(settings, geoip, passive_dns);
+    let mut fragments = FragmentReassembler::new(&options.reassembly);
+    let capture_file = crate::network_capture::pcap_reader(&input, options, &mut fragments, &mut |packet| {
+        accumulator.add_packet(&packet);
+    })?;
+    accumulator.add_fragmentation(&fragments.finish());
+
+    if export_flows {
+        crate::util::write_json_file(flows_output_path(&output), &accumulator.flows())?;
+    }
+    // Da qui in poi l'accumulatore contiene solo aggregati, anche quello inviato all'aggregatore.
+    accumulator.fold_connections();
+
+    let mut stats = accumulator.to_stats();
+    stats.capture_file = Some(capture_file);
     crate::util::write_json_file(&output, &stats)?;
-    Ok(packets)
+    Ok(accumulator)
+}
+
+/// Il file dei flussi affianca quello delle statistiche: "x.pcap.json" -> "x.pcap.flows.json".
+fn flows_output_path(output: &str) -> String {
+    match output.strip_suffix(".json") {
+        Some(base) => format!("{}.flows.json", base),
+        None => format!("{}.flows.json", output),
+    }
 }
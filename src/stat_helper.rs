@@ -1,15 +1,30 @@
-use crate::model::{PacketData, NetworkStats, ProtocolKey};
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::model::{PacketData, NetworkStats, ProtocolKey, TunnelStats, TlsStats, DnsStats, HttpStats,
+    CaptureTiming, TimeBucket, StatsConfig, FlowRecord, TrafficCounter, TopEntry, TopRanking,
+    IpNetwork, DirectionStats, TrafficDirection, GeoInfo,
+    TcpHealthStats, FragmentationStats};
+use crate::dns_helper::{query_type_name, response_code_name, PassiveDnsTable, SharedPassiveDns};
+use crate::http_helper::status_class;
+use crate::flow_helper::{merge_top_flows, FlowTable};
+use crate::tcp_helper::TcpHealth;
+use crate::detection_helper::ScanDetector;
+use crate::geoip_helper::GeoIpDatabase;
+use crate::subnet_helper::{home_networks, subnet_key, traffic_direction, zone_of};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Arc;
 
-fn invert_key_value<T: Ord + Clone>(map: HashMap<T, u32>) -> BTreeMap<u32, BTreeSet<T>> {
+const DNS_RCODE_NXDOMAIN: u8 = 3;
+
+fn invert_key_value<T: Ord + Clone>(map: &HashMap<T, u32>) -> BTreeMap<u32, BTreeSet<T>> {
     let mut frequency_map: BTreeMap<u32, BTreeSet<T>> = BTreeMap::new();
     for (key, freq) in map {
-        frequency_map.entry(freq).or_default().insert(key);
+        frequency_map.entry(*freq).or_default().insert(key.clone());
     }
     frequency_map
 }
 
-fn top_n_by_frequency<T: Ord + Clone>(map: HashMap<T, u32>, n: usize) -> Vec<T> {
+fn top_n_by_frequency<T: Ord + Clone>(map: &HashMap<T, u32>, n: usize) -> Vec<T> {
     let inverted = invert_key_value(map);
     let mut result = Vec::new();
 
@@ -25,42 +40,744 @@ fn top_n_by_frequency<T: Ord + Clone>(map: HashMap<T, u32>, n: usize) -> Vec<T>
     result
 }
 
-pub fn generate_stats(data_packets: &Vec<PacketData>) -> NetworkStats {
-    let mut stats = NetworkStats {
-        total_packets: 0,
-        total_bytes_packet: 0,
-        by_protocol: HashMap::new(),
-        top_10_ips: Vec::new(),
-        top_10_ports: Vec::new(),
+fn percentage(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+/// Classifica le chiavi per pacchetti e per byte. A parità di valore le
+/// chiavi sono ordinate in modo crescente, come in `top_n_by_frequency`.
+fn top_n_ranking<T: Ord + Clone>(
+    map: &HashMap<T, TrafficCounter>,
+    n: usize,
+    total_packets: u64,
+    total_bytes: u64,
+) -> TopRanking<T> {
+    let to_entries = |mut keys: Vec<(&T, &TrafficCounter)>| -> Vec<TopEntry<T>> {
+        keys.truncate(n);
+        keys.into_iter()
+            .map(|(key, counter)| TopEntry {
+                key: key.clone(),
+                packets: counter.packets,
+                bytes: counter.bytes,
+                packets_percentage: percentage(counter.packets, total_packets),
+                bytes_percentage: percentage(counter.bytes, total_bytes),
+                geo: None,
+                hostname: None,
+            })
+            .collect()
     };
 
-    let mut ip_freq: HashMap<String, u32> = HashMap::new();
-    let mut port_freq: HashMap<u16, u32> = HashMap::new();
+    let mut by_packets: Vec<(&T, &TrafficCounter)> = map.iter().collect();
+    by_packets.sort_by(|a, b| b.1.packets.cmp(&a.1.packets).then_with(|| a.0.cmp(b.0)));
+    let mut by_bytes: Vec<(&T, &TrafficCounter)> = map.iter().collect();
+    by_bytes.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(b.0)));
+
+    TopRanking {
+        by_packets: to_entries(by_packets),
+        by_bytes: to_entries(by_bytes),
+    }
+}
 
-    stats.total_packets = data_packets.len();
+/// I tag impilati (QinQ) vengono riportati come "esterno.interno".
+fn vlan_key(vlan_ids: &[u16]) -> String {
+    vlan_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(".")
+}
 
-    for packet in data_packets.iter() {
+fn count_protocols(by_protocol: &mut HashMap<ProtocolKey, u32>, packet: &PacketData) {
+    *by_protocol.entry(ProtocolKey::Internet(packet.internet_layer.clone())).or_insert(0) += 1;
+
+    if let Some(transport_layer) = packet.transport_layer.clone() {
+        *by_protocol.entry(ProtocolKey::Transport(transport_layer)).or_insert(0) += 1;
+    }
+
+    if let Some(application_layer) = packet.application_layer.clone() {
+        *by_protocol.entry(ProtocolKey::Application(application_layer)).or_insert(0) += 1;
+    }
+}
+
+fn merge_counts<T: Eq + Hash>(into: &mut HashMap<T, u32>, from: HashMap<T, u32>) {
+    for (key, count) in from {
+        *into.entry(key).or_insert(0) += count;
+    }
+}
+
+fn merge_traffic<T: Eq + Hash>(into: &mut HashMap<T, TrafficCounter>, from: HashMap<T, TrafficCounter>) {
+    for (key, counter) in from {
+        into.entry(key).or_default().merge(&counter);
+    }
+}
+
+/// Riduce la mappa alle `limit` chiavi migliori per pacchetti e per byte
+/// quando supera di molto il limite. È un'approssimazione: i totali restano
+/// esatti, ma una chiave scartata perde i conteggi accumulati e, se torna,
+/// riparte da zero. Finché le chiavi distinte restano entro `4 * limit` le
+/// classifiche coincidono con un ricalcolo completo.
+fn prune_traffic<T: Ord + Clone + Hash>(map: &mut HashMap<T, TrafficCounter>, limit: usize) {
+    if map.len() <= limit.saturating_mul(4) {
+        return;
+    }
+    let mut keep: HashSet<T> = HashSet::with_capacity(limit * 2);
+    let mut entries: Vec<(&T, &TrafficCounter)> = map.iter().collect();
+    entries.select_nth_unstable_by(limit, |a, b| b.1.packets.cmp(&a.1.packets).then_with(|| a.0.cmp(b.0)));
+    keep.extend(entries[..limit].iter().map(|(key, _)| (*key).clone()));
+    entries.select_nth_unstable_by(limit, |a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(b.0)));
+    keep.extend(entries[..limit].iter().map(|(key, _)| (*key).clone()));
+    map.retain(|key, _| keep.contains(key));
+}
+
+fn prune_counts<T: Ord + Clone + Hash>(map: &mut HashMap<T, u32>, limit: usize) {
+    if map.len() <= limit.saturating_mul(4) {
+        return;
+    }
+    let mut entries: Vec<(&T, &u32)> = map.iter().collect();
+    entries.select_nth_unstable_by(limit, |a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let keep: HashSet<T> = entries[..limit].iter().map(|(key, _)| (*key).clone()).collect();
+    map.retain(|key, _| keep.contains(key));
+}
+
+fn ensure_geo_cached(cache: &mut HashMap<String, GeoInfo>, database: &GeoIpDatabase, ip: &str) {
+    if !cache.contains_key(ip) {
+        let info = ip.parse::<IpAddr>().map(|ip| database.lookup(&ip)).unwrap_or_default();
+        cache.insert(ip.to_string(), info);
+    }
+}
+
+/// Conta il pacchetto una sola volta per chiave, anche se sorgente e
+/// destinazione ricadono nello stesso paese o nello stesso ASN.
+fn count_endpoint_keys(
+    map: &mut HashMap<String, TrafficCounter>,
+    source_key: Option<String>,
+    destination_key: Option<String>,
+    bytes: u64,
+) {
+    if let Some(key) = &source_key {
+        map.entry(key.clone()).or_default().add(bytes);
+    }
+    if let Some(key) = destination_key
+        && source_key.as_ref() != Some(&key)
+    {
+        map.entry(key).or_default().add(bytes);
+    }
+}
+
+fn capture_timing(
+    first_packet_us: Option<u64>,
+    last_packet_us: Option<u64>,
+    total_packets: usize,
+    total_bytes: u64,
+) -> CaptureTiming {
+    let duration_secs = match (first_packet_us, last_packet_us) {
+        (Some(first), Some(last)) => (last - first) as f64 / 1_000_000.0,
+        _ => 0.0,
+    };
+    let (avg_packets_per_sec, avg_bytes_per_sec) = if duration_secs > 0.0 {
+        (total_packets as f64 / duration_secs, total_bytes as f64 / duration_secs)
+    } else {
+        (0.0, 0.0)
+    };
+
+    CaptureTiming {
+        first_packet_us,
+        last_packet_us,
+        duration_secs,
+        avg_packets_per_sec,
+        avg_bytes_per_sec,
+    }
+}
+
+/// Statistiche calcolate in modo incrementale: ogni pacchetto viene contato
+/// appena letto e poi scartato. Lo stato per connessione (flussi, tentativi
+/// TCP) vive solo durante la lettura di un file: `fold_connections` lo riduce
+/// a contatori aggregati e a una classifica limitata dei flussi. Gli
+/// accumulatori di file diversi si uniscono con `merge`; le mappe per chiave
+/// dell'aggregatore vengono limitate a `max_tracked_keys` voci e la serie
+/// temporale agli ultimi `max_time_buckets` intervalli.
+pub struct StatsAccumulator {
+    bucket_us: u64,
+    max_time_buckets: usize,
+    top_n: usize,
+    max_tracked_keys: usize,
+    zones: HashMap<String, Vec<IpNetwork>>,
+    home_networks: Vec<IpNetwork>,
+    geoip: Option<Arc<GeoIpDatabase>>,
+    geo_cache: HashMap<String, GeoInfo>,
+    passive_dns: PassiveDnsTable,
+    shared_dns: SharedPassiveDns,
+    stats: NetworkStats,
+    time_buckets: BTreeMap<u64, TimeBucket>,
+    flows: FlowTable,
+    server_port_freq: HashMap<String, TrafficCounter>,
+    tcp_health: TcpHealth,
+    top_flows: Vec<FlowRecord>,
+    scans: ScanDetector,
+    ip_freq: HashMap<String, TrafficCounter>,
+    port_freq: HashMap<u16, TrafficCounter>,
+    source_freq: HashMap<String, TrafficCounter>,
+    destination_freq: HashMap<String, TrafficCounter>,
+    conversation_freq: HashMap<String, TrafficCounter>,
+    subnet_freq: HashMap<String, TrafficCounter>,
+    remote_peer_freq: HashMap<TrafficDirection, HashMap<String, TrafficCounter>>,
+    tunnel_endpoint_freq: HashMap<String, u32>,
+    server_name_freq: HashMap<String, u32>,
+    queried_name_freq: HashMap<String, u32>,
+    nxdomain_responses: u64,
+    http_host_freq: HashMap<String, u32>,
+    http_path_freq: HashMap<String, u32>,
+    user_agent_freq: HashMap<String, u32>,
+}
+
+impl StatsAccumulator {
+    pub fn new(settings: &StatsConfig, geoip: Option<Arc<GeoIpDatabase>>, shared_dns: SharedPassiveDns) -> Self {
+        StatsAccumulator {
+            bucket_us: settings.time_bucket_secs.max(1) * 1_000_000,
+            max_time_buckets: settings.max_time_buckets,
+            top_n: settings.top_n,
+            max_tracked_keys: settings.max_tracked_keys.max(settings.top_n),
+            zones: settings.zones.clone(),
+            home_networks: home_networks(&settings.home_networks, settings.private_networks_are_home),
+            geoip,
+            geo_cache: HashMap::new(),
+            passive_dns: PassiveDnsTable::default(),
+            shared_dns,
+            stats: NetworkStats {
+                total_packets: 0,
+                total_bytes_packet: 0,
+                total_bytes_captured: 0,
+                truncated_packets: 0,
+                truncated_bytes_lost: 0,
+                by_protocol: HashMap::new(),
+                top_ips: TopRanking::default(),
+                top_ports: TopRanking::default(),
+                top_sources: TopRanking::default(),
+                top_destinations: TopRanking::default(),
+                top_server_ports: TopRanking::default(),
+                top_conversations: TopRanking::default(),
+                top_subnets: TopRanking::default(),
+                by_zone: HashMap::new(),
+                zone_matrix: HashMap::new(),
+                directions: DirectionStats::default(),
+                by_country: HashMap::new(),
+                by_asn: HashMap::new(),
+                detections: Vec::new(),
+                tcp: TcpHealthStats::default(),
+                fragmentation: FragmentationStats::default(),
+                by_vlan: HashMap::new(),
+                tunnels: TunnelStats::default(),
+                by_icmp_type: HashMap::new(),
+                tls: TlsStats::default(),
+                dns: DnsStats::default(),
+                http: HttpStats::default(),
+                top_flows: Vec::new(),
+                capture: CaptureTiming::default(),
+                time_series: Vec::new(),
+                by_interface: HashMap::new(),
+                capture_file: None,
+            },
+            time_buckets: BTreeMap::new(),
+            flows: FlowTable::default(),
+            server_port_freq: HashMap::new(),
+            tcp_health: TcpHealth::default(),
+            top_flows: Vec::new(),
+            scans: ScanDetector::new(&settings.detection),
+            ip_freq: HashMap::new(),
+            port_freq: HashMap::new(),
+            source_freq: HashMap::new(),
+            destination_freq: HashMap::new(),
+            conversation_freq: HashMap::new(),
+            subnet_freq: HashMap::new(),
+            remote_peer_freq: HashMap::new(),
+            tunnel_endpoint_freq: HashMap::new(),
+            server_name_freq: HashMap::new(),
+            queried_name_freq: HashMap::new(),
+            nxdomain_responses: 0,
+            http_host_freq: HashMap::new(),
+            http_path_freq: HashMap::new(),
+            user_agent_freq: HashMap::new(),
+        }
+    }
+
+    pub fn add_packet(&mut self, packet: &PacketData) {
+        let stats = &mut self.stats;
+
+        stats.total_packets += 1;
         stats.total_bytes_packet += packet.packet_length as u64;
+        stats.total_bytes_captured += packet.captured_length as u64;
+        if packet.captured_length < packet.packet_length {
+            stats.truncated_packets += 1;
+            stats.truncated_bytes_lost += (packet.packet_length - packet.captured_length) as u64;
+        }
+
+        count_protocols(&mut stats.by_protocol, packet);
+
+        let first_packet_us = stats.capture.first_packet_us.get_or_insert(packet.timestamp_us);
+        *first_packet_us = (*first_packet_us).min(packet.timestamp_us);
+        let last_packet_us = stats.capture.last_packet_us.get_or_insert(packet.timestamp_us);
+        *last_packet_us = (*last_packet_us).max(packet.timestamp_us);
+
+        let bucket_start_us = packet.timestamp_us - packet.timestamp_us % self.bucket_us;
+        let bucket = self.time_buckets.entry(bucket_start_us).or_insert_with(|| TimeBucket {
+            start_us: bucket_start_us,
+            packets: 0,
+            bytes: 0,
+            by_protocol: HashMap::new(),
+        });
+        bucket.packets += 1;
+        bucket.bytes += packet.packet_length as u64;
+        count_protocols(&mut bucket.by_protocol, packet);
+
+        self.flows.add_packet(packet);
+        self.scans.add_packet(packet);
+
+        if let Some(interface) = &packet.interface {
+            stats.by_interface.entry(interface.clone()).or_default().add(packet.packet_length as u64);
+        }
+
+        if !packet.vlan_ids.is_empty() {
+            stats.by_vlan.entry(vlan_key(&packet.vlan_ids)).or_default().add(packet.packet_length as u64);
+        }
+
+        for tunnel in packet.tunnels.iter() {
+            stats.tunnels.by_encapsulation.entry(tunnel.encapsulation.clone()).or_default().add(packet.packet_length as u64);
+            let endpoints = format!("{} -> {}", tunnel.outer_source_ip, tunnel.outer_destination_ip);
+            *self.tunnel_endpoint_freq.entry(endpoints).or_insert(0) += 1;
+        }
 
-        *stats.by_protocol.entry(ProtocolKey::Internet(packet.internet_layer.clone())).or_insert(0) += 1;
+        if let Some(tls) = &packet.tls {
+            stats.tls.client_hellos += 1;
+            if let Some(server_name) = &tls.server_name {
+                *self.server_name_freq.entry(server_name.clone()).or_insert(0) += 1;
+            }
+            if let Some(version) = &tls.version {
+                *stats.tls.by_version.entry(version.clone()).or_insert(0) += 1;
+            }
+            for alpn in tls.alpn.iter() {
+                *stats.tls.by_alpn.entry(alpn.clone()).or_insert(0) += 1;
+            }
+        }
 
-        if let Some(transport_layer) = packet.transport_layer.clone() {
-            *stats.by_protocol.entry(ProtocolKey::Transport(transport_layer)).or_insert(0) += 1;
+        if let Some(dns) = &packet.dns {
+            if dns.is_response {
+                self.passive_dns.record(dns, packet.timestamp_us);
+                stats.dns.responses += 1;
+                if dns.response_code == DNS_RCODE_NXDOMAIN {
+                    self.nxdomain_responses += 1;
+                }
+                *stats.dns.by_response_code.entry(response_code_name(dns.response_code)).or_insert(0) += 1;
+            } else {
+                stats.dns.queries += 1;
+                for question in dns.questions.iter() {
+                    *self.queried_name_freq.entry(question.name.clone()).or_insert(0) += 1;
+                    *stats.dns.by_query_type.entry(query_type_name(question.query_type)).or_insert(0) += 1;
+                }
+            }
+        }
+
+        if let Some(http) = &packet.http {
+            if let Some(method) = &http.method {
+                stats.http.requests += 1;
+                *stats.http.by_method.entry(method.clone()).or_insert(0) += 1;
+            }
+            if let Some(status_code) = http.status_code {
+                stats.http.responses += 1;
+                *stats.http.by_status_class.entry(status_class(status_code)).or_insert(0) += 1;
+            }
+            if let Some(host) = &http.host {
+                *self.http_host_freq.entry(host.clone()).or_insert(0) += 1;
+            }
+            if let Some(path) = &http.path {
+                *self.http_path_freq.entry(path.clone()).or_insert(0) += 1;
+            }
+            if let Some(user_agent) = &http.user_agent {
+                *self.user_agent_freq.entry(user_agent.clone()).or_insert(0) += 1;
+            }
         }
 
-        if let Some(application_layer) = packet.application_layer.clone() {
-            *stats.by_protocol.entry(ProtocolKey::Application(application_layer)).or_insert(0) += 1;
+        let bytes = packet.packet_length as u64;
+        self.ip_freq.entry(packet.source_ip.clone()).or_default().add(bytes);
+        if packet.destination_ip != packet.source_ip {
+            self.ip_freq.entry(packet.destination_ip.clone()).or_default().add(bytes);
         }
 
-        *ip_freq.entry(packet.source_ip.clone()).or_insert(0) += 1;
-        *ip_freq.entry(packet.destination_ip.clone()).or_insert(0) += 1;
+        self.source_freq.entry(packet.source_ip.clone()).or_default().add(bytes);
+        self.destination_freq.entry(packet.destination_ip.clone()).or_default().add(bytes);
+        let conversation = format!("{} -> {}", packet.source_ip, packet.destination_ip);
+        self.conversation_freq.entry(conversation).or_default().add(bytes);
 
-        *port_freq.entry(packet.source_port).or_insert(0) += 1;
-        *port_freq.entry(packet.destination_port).or_insert(0) += 1;
+        if let (Ok(source), Ok(destination)) =
+            (packet.source_ip.parse::<IpAddr>(), packet.destination_ip.parse::<IpAddr>())
+        {
+            let source_subnet = subnet_key(&source);
+            let destination_subnet = subnet_key(&destination);
+            if destination_subnet != source_subnet {
+                self.subnet_freq.entry(destination_subnet).or_default().add(bytes);
+            }
+            self.subnet_freq.entry(source_subnet).or_default().add(bytes);
+
+            let direction = traffic_direction(&self.home_networks, &source, &destination);
+            let direction_traffic = stats.directions.get_mut(direction);
+            direction_traffic.packets += 1;
+            direction_traffic.bytes += bytes;
+            let remote_peers = self.remote_peer_freq.entry(direction).or_default();
+            match direction {
+                TrafficDirection::Inbound => remote_peers.entry(packet.source_ip.clone()).or_default().add(bytes),
+                TrafficDirection::Outbound => remote_peers.entry(packet.destination_ip.clone()).or_default().add(bytes),
+                TrafficDirection::ExternalTransit => {
+                    remote_peers.entry(packet.source_ip.clone()).or_default().add(bytes);
+                    if packet.destination_ip != packet.source_ip {
+                        remote_peers.entry(packet.destination_ip.clone()).or_default().add(bytes);
+                    }
+                }
+                TrafficDirection::Internal => {}
+            }
+
+            if !self.zones.is_empty() {
+                let source_zone = zone_of(&self.zones, &source);
+                let destination_zone = zone_of(&self.zones, &destination);
+                stats.by_zone.entry(source_zone.to_string()).or_default().add(bytes);
+                if destination_zone != source_zone {
+                    stats.by_zone.entry(destination_zone.to_string()).or_default().add(bytes);
+                }
+                stats.zone_matrix
+                    .entry(source_zone.to_string())
+                    .or_default()
+                    .entry(destination_zone.to_string())
+                    .or_default()
+                    .add(bytes);
+            }
+        }
+
+        if let Some(database) = &self.geoip {
+            ensure_geo_cached(&mut self.geo_cache, database, &packet.source_ip);
+            ensure_geo_cached(&mut self.geo_cache, database, &packet.destination_ip);
+            let source_geo = &self.geo_cache[&packet.source_ip];
+            let destination_geo = &self.geo_cache[&packet.destination_ip];
+            count_endpoint_keys(&mut stats.by_country, source_geo.country.clone(), destination_geo.country.clone(), bytes);
+            count_endpoint_keys(&mut stats.by_asn, source_geo.asn_key(), destination_geo.asn_key(), bytes);
+        }
+
+        if packet.has_ports() {
+            self.port_freq.entry(packet.source_port).or_default().add(bytes);
+            if packet.destination_port != packet.source_port {
+                self.port_freq.entry(packet.destination_port).or_default().add(bytes);
+            }
+        }
+
+        if let (Some(icmp), Some(transport_layer)) = (&packet.icmp, &packet.transport_layer) {
+            let icmp_key = format!("{} {}/{}", transport_layer, icmp.icmp_type, icmp.code);
+            *stats.by_icmp_type.entry(icmp_key).or_insert(0) += 1;
+        }
+    }
+
+    /// Registra i contatori di frammentazione del riassemblatore di un file.
+    pub fn add_fragmentation(&mut self, fragmentation: &FragmentationStats) {
+        self.stats.fragmentation.merge(fragmentation);
+    }
+
+    /// Riduce flussi e tentativi di connessione del file ai soli aggregati
+    /// (porte dei server, salute TCP, flussi candidati alla classifica,
+    /// obiettivi per sorgente), così che l'aggregatore non riceva stato per
+    /// connessione. I candidati sono i `max_tracked_keys` flussi maggiori:
+    /// la classifica di `top_n` voci si ricava solo in `to_stats`.
+    pub fn fold_connections(&mut self) {
+        let flows = std::mem::take(&mut self.flows);
+        let limit = self.max_tracked_keys;
+        merge_traffic(&mut self.server_port_freq, flows.server_ports());
+        self.tcp_health.merge(&flows.tcp_health());
+        merge_top_flows(&mut self.top_flows, flows.top_records(limit), limit);
+        self.scans.fold();
+    }
+
+    /// Aggiunge i conteggi di un altro accumulatore, tipicamente quello di
+    /// un singolo file prodotto da un worker.
+    pub fn merge(&mut self, mut other: StatsAccumulator) {
+        other.fold_connections();
+        let stats = &mut self.stats;
+        let other_stats = other.stats;
+
+        stats.total_packets += other_stats.total_packets;
+        stats.total_bytes_packet += other_stats.total_bytes_packet;
+        stats.total_bytes_captured += other_stats.total_bytes_captured;
+        stats.truncated_packets += other_stats.truncated_packets;
+        stats.truncated_bytes_lost += other_stats.truncated_bytes_lost;
+        stats.fragmentation.merge(&other_stats.fragmentation);
+        merge_counts(&mut stats.by_protocol, other_stats.by_protocol);
+        merge_traffic(&mut stats.by_vlan, other_stats.by_vlan);
+        merge_traffic(&mut stats.tunnels.by_encapsulation, other_stats.tunnels.by_encapsulation);
+        merge_counts(&mut stats.by_icmp_type, other_stats.by_icmp_type);
+        merge_traffic(&mut stats.by_interface, other_stats.by_interface);
+        merge_traffic(&mut stats.by_zone, other_stats.by_zone);
+        merge_traffic(&mut stats.by_country, other_stats.by_country);
+        merge_traffic(&mut stats.by_asn, other_stats.by_asn);
+        for (source_zone, destinations) in other_stats.zone_matrix {
+            merge_traffic(stats.zone_matrix.entry(source_zone).or_default(), destinations);
+        }
+        let mut other_directions = other_stats.directions;
+        for direction in [
+            TrafficDirection::Inbound,
+            TrafficDirection::Outbound,
+            TrafficDirection::Internal,
+            TrafficDirection::ExternalTransit,
+        ] {
+            let other_traffic = other_directions.get_mut(direction);
+            let traffic = stats.directions.get_mut(direction);
+            traffic.packets += other_traffic.packets;
+            traffic.bytes += other_traffic.bytes;
+        }
+
+        stats.tls.client_hellos += other_stats.tls.client_hellos;
+        merge_counts(&mut stats.tls.by_version, other_stats.tls.by_version);
+        merge_counts(&mut stats.tls.by_alpn, other_stats.tls.by_alpn);
+
+        stats.dns.queries += other_stats.dns.queries;
+        stats.dns.responses += other_stats.dns.responses;
+        merge_counts(&mut stats.dns.by_query_type, other_stats.dns.by_query_type);
+        merge_counts(&mut stats.dns.by_response_code, other_stats.dns.by_response_code);
+        self.nxdomain_responses += other.nxdomain_responses;
+
+        stats.http.requests += other_stats.http.requests;
+        stats.http.responses += other_stats.http.responses;
+        merge_counts(&mut stats.http.by_method, other_stats.http.by_method);
+        merge_counts(&mut stats.http.by_status_class, other_stats.http.by_status_class);
+
+        stats.capture.first_packet_us = match (stats.capture.first_packet_us, other_stats.capture.first_packet_us) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        stats.capture.last_packet_us = stats.capture.last_packet_us.max(other_stats.capture.last_packet_us);
+
+        for (start_us, other_bucket) in other.time_buckets {
+            match self.time_buckets.get_mut(&start_us) {
+                Some(bucket) => {
+                    bucket.packets += other_bucket.packets;
+                    bucket.bytes += other_bucket.bytes;
+                    merge_counts(&mut bucket.by_protocol, other_bucket.by_protocol);
+                }
+                None => {
+                    self.time_buckets.insert(start_us, other_bucket);
+                }
+            }
+        }
+        while self.time_buckets.len() > self.max_time_buckets {
+            self.time_buckets.pop_first();
+        }
+
+        merge_traffic(&mut self.server_port_freq, other.server_port_freq);
+        self.tcp_health.merge(&other.tcp_health);
+        merge_top_flows(&mut self.top_flows, other.top_flows, self.max_tracked_keys);
+        self.scans.merge(other.scans);
+        // Le risoluzioni del file diventano visibili ai worker per i file successivi.
+        {
+            let mut shared_dns = self.shared_dns.write().unwrap();
+            shared_dns.merge(other.passive_dns);
+            shared_dns.prune(self.max_tracked_keys);
+        }
+        merge_traffic(&mut self.ip_freq, other.ip_freq);
+        merge_traffic(&mut self.port_freq, other.port_freq);
+        merge_traffic(&mut self.source_freq, other.source_freq);
+        merge_traffic(&mut self.destination_freq, other.destination_freq);
+        merge_traffic(&mut self.conversation_freq, other.conversation_freq);
+        merge_traffic(&mut self.subnet_freq, other.subnet_freq);
+        for (direction, peers) in other.remote_peer_freq {
+            merge_traffic(self.remote_peer_freq.entry(direction).or_default(), peers);
+        }
+        merge_counts(&mut self.tunnel_endpoint_freq, other.tunnel_endpoint_freq);
+        merge_counts(&mut self.server_name_freq, other.server_name_freq);
+        merge_counts(&mut self.queried_name_freq, other.queried_name_freq);
+        merge_counts(&mut self.http_host_freq, other.http_host_freq);
+        merge_counts(&mut self.http_path_freq, other.http_path_freq);
+        merge_counts(&mut self.user_agent_freq, other.user_agent_freq);
+
+        let limit = self.max_tracked_keys;
+        for map in [
+            &mut self.ip_freq,
+            &mut self.source_freq,
+            &mut self.destination_freq,
+            &mut self.conversation_freq,
+            &mut self.subnet_freq,
+        ] {
+            prune_traffic(map, limit);
+        }
+        for peers in self.remote_peer_freq.values_mut() {
+            prune_traffic(peers, limit);
+        }
+        for map in [
+            &mut self.tunnel_endpoint_freq,
+            &mut self.server_name_freq,
+            &mut self.queried_name_freq,
+            &mut self.http_host_freq,
+            &mut self.http_path_freq,
+            &mut self.user_agent_freq,
+        ] {
+            prune_counts(map, limit);
+        }
+    }
+
+    /// Aggiunge paese, città e ASN alle voci di una classifica di indirizzi.
+    /// L'aggregatore non ha una cache propria e interroga direttamente il database.
+    fn annotate_geo(&self, ranking: &mut TopRanking<String>) {
+        let Some(database) = &self.geoip else { return };
+        for entry in ranking.by_packets.iter_mut().chain(ranking.by_bytes.iter_mut()) {
+            entry.geo = match self.geo_cache.get(&entry.key) {
+                Some(info) => Some(info.clone()),
+                None => entry.key.parse::<IpAddr>().ok().map(|ip| database.lookup(&ip)),
+            };
+        }
+    }
+
+    /// Nome risolto nel file corrente o, in mancanza, in quelli precedenti.
+    fn hostname(&self, address: &str) -> Option<String> {
+        self.passive_dns
+            .hostname(address)
+            .or_else(|| self.shared_dns.read().unwrap().hostname(address))
+    }
+
+    fn label_hosts(&self, ranking: &mut TopRanking<String>) {
+        for entry in ranking.by_packets.iter_mut().chain(ranking.by_bytes.iter_mut()) {
+            entry.hostname = self.hostname(&entry.key);
+        }
+    }
+
+    fn label_conversations(&self, ranking: &mut TopRanking<String>) {
+        for entry in ranking.by_packets.iter_mut().chain(ranking.by_bytes.iter_mut()) {
+            let Some((source, destination)) = entry.key.split_once(" -> ") else { continue };
+            let source_name = self.hostname(source);
+            let destination_name = self.hostname(destination);
+            if source_name.is_some() || destination_name.is_some() {
+                entry.hostname = Some(format!(
+                    "{} -> {}",
+                    source_name.as_deref().unwrap_or(source),
+                    destination_name.as_deref().unwrap_or(destination),
+                ));
+            }
+        }
+    }
+
+    fn label_flows(&self, flows: &mut [FlowRecord]) {
+        for flow in flows.iter_mut() {
+            flow.client_hostname = self.hostname(&flow.client_ip);
+            flow.server_hostname = self.hostname(&flow.server_ip);
+        }
+    }
+
+    /// Tutti i flussi osservati, ordinati per volume di byte.
+    pub fn flows(&self) -> Vec<FlowRecord> {
+        let mut flows = self.flows.records();
+        self.label_flows(&mut flows);
+        flows
+    }
+
+    /// Produce le statistiche per i pacchetti contati finora. Flussi e
+    /// tentativi di connessione devono essere già ridotti con `fold_connections`.
+    pub fn to_stats(&self) -> NetworkStats {
+        let mut stats = self.stats.clone();
+
+        let total_packets = stats.total_packets as u64;
+        stats.top_ips = top_n_ranking(&self.ip_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        stats.top_ports = top_n_ranking(&self.port_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        stats.top_sources = top_n_ranking(&self.source_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        stats.top_destinations = top_n_ranking(&self.destination_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        stats.top_server_ports = top_n_ranking(&self.server_port_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        stats.top_conversations = top_n_ranking(&self.conversation_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        stats.top_subnets = top_n_ranking(&self.subnet_freq, self.top_n, total_packets, stats.total_bytes_packet);
+        for (direction, peers) in self.remote_peer_freq.iter() {
+            let traffic = stats.directions.get_mut(*direction);
+            traffic.top_remote_peers = top_n_ranking(peers, self.top_n, traffic.packets, traffic.bytes);
+        }
+        for ranking in [
+            &mut stats.top_ips,
+            &mut stats.top_sources,
+            &mut stats.top_destinations,
+            &mut stats.directions.inbound.top_remote_peers,
+            &mut stats.directions.outbound.top_remote_peers,
+            &mut stats.directions.external_transit.top_remote_peers,
+        ] {
+            self.label_hosts(ranking);
+        }
+        self.label_conversations(&mut stats.top_conversations);
+        if self.geoip.is_some() {
+            self.annotate_geo(&mut stats.top_ips);
+            self.annotate_geo(&mut stats.top_sources);
+            self.annotate_geo(&mut stats.top_destinations);
+            self.annotate_geo(&mut stats.directions.inbound.top_remote_peers);
+            self.annotate_geo(&mut stats.directions.outbound.top_remote_peers);
+            self.annotate_geo(&mut stats.directions.external_transit.top_remote_peers);
+        }
+        stats.tunnels.top_10_endpoints = top_n_by_frequency(&self.tunnel_endpoint_freq, 10);
+        stats.tls.top_10_server_names = top_n_by_frequency(&self.server_name_freq, 10);
+        stats.dns.top_10_queried_names = top_n_by_frequency(&self.queried_name_freq, 10);
+        stats.http.top_10_hosts = top_n_by_frequency(&self.http_host_freq, 10);
+        stats.http.top_10_paths = top_n_by_frequency(&self.http_path_freq, 10);
+        stats.http.top_10_user_agents = top_n_by_frequency(&self.user_agent_freq, 10);
+        stats.capture = capture_timing(
+            stats.capture.first_packet_us,
+            stats.capture.last_packet_us,
+            stats.total_packets,
+            stats.total_bytes_packet,
+        );
+        stats.time_series = self.time_buckets.values().cloned().collect();
+        stats.top_flows = self.top_flows.iter().take(self.top_n).cloned().collect();
+        self.label_flows(&mut stats.top_flows);
+        stats.detections = self.scans.detections();
+        stats.tcp = self.tcp_health.to_stats();
+        if stats.dns.responses > 0 {
+            stats.dns.nxdomain_ratio = self.nxdomain_responses as f64 / stats.dns.responses as f64;
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TCP_ACK;
+    use std::sync::RwLock;
+
+    fn accumulator(settings: &StatsConfig) -> StatsAccumulator {
+        StatsAccumulator::new(settings, None, Arc::new(RwLock::new(PassiveDnsTable::default())))
+    }
+
+    fn bucket(start_us: u64) -> TimeBucket {
+        TimeBucket { start_us, packets: 1, bytes: 100, by_protocol: HashMap::new() }
     }
 
-    stats.top_10_ips = top_n_by_frequency(ip_freq, 10);
-    stats.top_10_ports = top_n_by_frequency(port_freq, 10);
+    #[test]
+    fn aggregate_time_series_keeps_latest_buckets() {
+        let settings = StatsConfig { max_time_buckets: 3, ..StatsConfig::default() };
+        let mut total = accumulator(&settings);
+        for file in 0..4u64 {
+            let mut other = accumulator(&settings);
+            for minute in 0..2 {
+                let start_us = (file * 2 + minute) * 60_000_000;
+                other.time_buckets.insert(start_us, bucket(start_us));
+            }
+            total.merge(other);
+        }
 
-    stats
-}
\ No newline at end of file
+        let series: Vec<u64> = total.to_stats().time_series.iter().map(|bucket| bucket.start_us / 60_000_000).collect();
+        assert_eq!(series, vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn flow_below_each_file_top_n_can_lead_the_total() {
+        let settings = StatsConfig { top_n: 10, ..StatsConfig::default() };
+        let mut total = accumulator(&settings);
+        for file in 0..5u16 {
+            let mut other = accumulator(&settings);
+            for port in 0..10 {
+                let client = ("10.0.0.2", 20000 + file * 100 + port);
+                other.flows.add_packet(&PacketData::tcp_segment(client, ("10.0.0.1", 443), TCP_ACK, 1000, 0));
+            }
+            // Undicesimo in ogni file, primo sul totale.
+            other.flows.add_packet(&PacketData::tcp_segment(("10.0.0.3", 40000), ("10.0.0.1", 22), TCP_ACK, 900, 0));
+            total.merge(other);
+        }
+
+        let top_flows = total.to_stats().top_flows;
+        assert_eq!(top_flows.len(), 10);
+        assert_eq!((top_flows[0].client_port, top_flows[0].bytes_to_server), (40000, 4500));
+    }
+}
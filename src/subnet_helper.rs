@@ -0,0 +1,66 @@
+use crate::model::{IpNetwork, TrafficDirection};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Zona assegnata agli indirizzi che non rientrano in nessuna rete configurata.
+pub const UNASSIGNED_ZONE: &str = "unassigned";
+
+/// RFC1918 e Unique Local Address (RFC4193).
+const PRIVATE_NETWORKS: [&str; 4] = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7"];
+
+const IPV4_ROLLUP_PREFIX: u8 = 24;
+const IPV6_ROLLUP_PREFIX: u8 = 64;
+
+/// Restituisce la zona con la rete più specifica che contiene l'indirizzo.
+/// A parità di prefisso vince il nome di zona minore, così il risultato non
+/// dipende dall'ordine della mappa.
+pub fn zone_of<'a>(zones: &'a HashMap<String, Vec<IpNetwork>>, ip: &IpAddr) -> &'a str {
+    let mut best: Option<(&str, u8)> = None;
+
+    for (name, networks) in zones {
+        for network in networks.iter().filter(|network| network.contains(ip)) {
+            let is_better = match best {
+                Some((best_name, best_len)) => {
+                    network.prefix_len > best_len
+                        || (network.prefix_len == best_len && name.as_str() < best_name)
+                }
+                None => true,
+            };
+            if is_better {
+                best = Some((name.as_str(), network.prefix_len));
+            }
+        }
+    }
+
+    best.map_or(UNASSIGNED_ZONE, |(name, _)| name)
+}
+
+/// Aggrega l'indirizzo nella sua /24 (IPv4) o /64 (IPv6).
+pub fn subnet_key(ip: &IpAddr) -> String {
+    let prefix_len = if ip.is_ipv4() { IPV4_ROLLUP_PREFIX } else { IPV6_ROLLUP_PREFIX };
+    match IpNetwork::new(*ip, prefix_len) {
+        Some(network) => network.to_string(),
+        None => ip.to_string(),
+    }
+}
+
+/// Reti interne configurate, più gli spazi privati se richiesto.
+pub fn home_networks(configured: &[IpNetwork], include_private: bool) -> Vec<IpNetwork> {
+    let mut networks = configured.to_vec();
+    if include_private {
+        networks.extend(PRIVATE_NETWORKS.iter().filter_map(|network| network.parse::<IpNetwork>().ok()));
+    }
+    networks
+}
+
+pub fn traffic_direction(home: &[IpNetwork], source: &IpAddr, destination: &IpAddr) -> TrafficDirection {
+    let source_is_home = home.iter().any(|network| network.contains(source));
+    let destination_is_home = home.iter().any(|network| network.contains(destination));
+
+    match (source_is_home, destination_is_home) {
+        (true, true) => TrafficDirection::Internal,
+        (true, false) => TrafficDirection::Outbound,
+        (false, true) => TrafficDirection::Inbound,
+        (false, false) => TrafficDirection::ExternalTransit,
+    }
+}
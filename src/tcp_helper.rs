@@ -0,0 +1,300 @@
+use crate::model::{TcpFlowStats, TcpHealthStats, TcpInfo, TCP_ACK, TCP_FIN, TCP_RST, TCP_SYN};
+
+/// Un segmento già coperto che arriva entro questo intervallo dall'ultimo
+/// avanzamento della sequenza è considerato fuori ordine, non ritrasmesso:
+/// nessun RTO è così breve.
+const OUT_OF_ORDER_WINDOW_US: u64 = 3_000;
+
+/// Confronto fra numeri di sequenza tenendo conto del wrap-around a 32 bit.
+fn sequence_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[derive(Default)]
+struct DirectionState {
+    next_sequence: Option<u32>,
+    last_advance_us: u64,
+    /// Il mittente ha annunciato una finestra nulla e non l'ha ancora riaperta.
+    zero_window: bool,
+}
+
+/// Stato di una sessione TCP all'interno di un flusso. Il client è quello
+/// del flusso, cioè l'endpoint che ha inviato il SYN se osservato.
+#[derive(Default)]
+pub struct TcpSession {
+    to_server: DirectionState,
+    to_client: DirectionState,
+    syn_us: Option<u64>,
+    client_isn: Option<u32>,
+    server_isn: Option<u32>,
+    handshake_rtt_us: Option<u64>,
+    segments: u64,
+    retransmissions: u64,
+    out_of_order: u64,
+    zero_window_events: u64,
+    fin_seen: bool,
+    rst_seen: bool,
+}
+
+impl TcpSession {
+    pub fn update(&mut self, flags: u8, tcp: &TcpInfo, to_server: bool, timestamp_us: u64) {
+        let is_syn = flags & TCP_SYN != 0;
+        let is_ack = flags & TCP_ACK != 0;
+
+        // Handshake: RTT misurato dal SYN del client all'ACK che chiude il
+        // three-way handshake. Ogni passo deve riscontrare l'ISN dell'altro lato.
+        if is_syn && !is_ack && to_server {
+            self.syn_us.get_or_insert(timestamp_us);
+            self.client_isn.get_or_insert(tcp.sequence);
+        } else if is_syn && is_ack && !to_server
+            && self.client_isn.map(|isn| isn.wrapping_add(1)) == Some(tcp.acknowledgment)
+        {
+            self.server_isn.get_or_insert(tcp.sequence);
+        } else if !is_syn && is_ack && to_server && self.handshake_rtt_us.is_none()
+            && self.server_isn.map(|isn| isn.wrapping_add(1)) == Some(tcp.acknowledgment)
+            && let Some(syn_us) = self.syn_us
+        {
+            self.handshake_rtt_us = Some(timestamp_us.saturating_sub(syn_us));
+        }
+
+        self.fin_seen |= flags & TCP_FIN != 0;
+        self.rst_seen |= flags & TCP_RST != 0;
+
+        // Conta solo l'ingresso in finestra nulla: i window probe di uno
+        // stallo ripetono lo stesso annuncio.
+        let direction = if to_server { &mut self.to_server } else { &mut self.to_client };
+        if flags & (TCP_SYN | TCP_RST | TCP_FIN) == 0 {
+            if tcp.window == 0 && !direction.zero_window {
+                self.zero_window_events += 1;
+            }
+            direction.zero_window = tcp.window == 0;
+        }
+
+        // SYN e FIN occupano un numero di sequenza come un byte di dati.
+        let segment_len = tcp.payload_len + is_syn as u32 + (flags & TCP_FIN != 0) as u32;
+        if segment_len == 0 {
+            return;
+        }
+        self.segments += 1;
+
+        let segment_end = tcp.sequence.wrapping_add(segment_len);
+        match direction.next_sequence {
+            Some(next_sequence) if !sequence_after(segment_end, next_sequence) => {
+                if timestamp_us.saturating_sub(direction.last_advance_us) < OUT_OF_ORDER_WINDOW_US {
+                    self.out_of_order += 1;
+                } else {
+                    self.retransmissions += 1;
+                }
+            }
+            _ => {
+                direction.next_sequence = Some(segment_end);
+                direction.last_advance_us = timestamp_us;
+            }
+        }
+    }
+
+    pub fn to_stats(&self) -> TcpFlowStats {
+        let closed_by = if self.rst_seen {
+            Some("rst".to_string())
+        } else if self.fin_seen {
+            Some("fin".to_string())
+        } else {
+            None
+        };
+
+        TcpFlowStats {
+            handshake_rtt_us: self.handshake_rtt_us,
+            segments: self.segments,
+            retransmissions: self.retransmissions,
+            out_of_order: self.out_of_order,
+            zero_window_events: self.zero_window_events,
+            closed_by,
+        }
+    }
+}
+
+/// Metriche aggregate sulle sessioni TCP. Conserva la somma degli RTT così
+/// che gli aggregati di file diversi si possano unire senza le sessioni.
+#[derive(Default, Clone)]
+pub struct TcpHealth {
+    stats: TcpHealthStats,
+    total_rtt_us: u64,
+}
+
+impl TcpHealth {
+    pub fn add_session(&mut self, session: &TcpSession) {
+        let health = &mut self.stats;
+        health.flows += 1;
+        if let Some(rtt_us) = session.handshake_rtt_us {
+            health.handshakes_completed += 1;
+            self.total_rtt_us += rtt_us;
+            health.max_handshake_rtt_us = health.max_handshake_rtt_us.max(Some(rtt_us));
+        }
+        health.segments += session.segments;
+        health.retransmissions += session.retransmissions;
+        health.out_of_order += session.out_of_order;
+        health.zero_window_events += session.zero_window_events;
+        if session.rst_seen {
+            health.reset_flows += 1;
+        } else if session.fin_seen {
+            health.fin_flows += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &TcpHealth) {
+        let health = &mut self.stats;
+        health.flows += other.stats.flows;
+        health.handshakes_completed += other.stats.handshakes_completed;
+        health.max_handshake_rtt_us = health.max_handshake_rtt_us.max(other.stats.max_handshake_rtt_us);
+        health.segments += other.stats.segments;
+        health.retransmissions += other.stats.retransmissions;
+        health.out_of_order += other.stats.out_of_order;
+        health.zero_window_events += other.stats.zero_window_events;
+        health.reset_flows += other.stats.reset_flows;
+        health.fin_flows += other.stats.fin_flows;
+        self.total_rtt_us += other.total_rtt_us;
+    }
+
+    pub fn to_stats(&self) -> TcpHealthStats {
+        let mut health = self.stats.clone();
+        if health.handshakes_completed > 0 {
+            health.avg_handshake_rtt_us = self.total_rtt_us as f64 / health.handshakes_completed as f64;
+        }
+        if health.segments > 0 {
+            health.retransmission_ratio = health.retransmissions as f64 / health.segments as f64;
+        }
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSH_ACK: u8 = 0x08 | TCP_ACK;
+
+    fn segment(sequence: u32, acknowledgment: u32, payload_len: u32) -> TcpInfo {
+        TcpInfo { sequence, acknowledgment, window: 65535, payload_len }
+    }
+
+    /// Three-way handshake con ISN 1000 per il client e 5000 per il server.
+    fn handshake(session: &mut TcpSession) {
+        session.update(TCP_SYN, &segment(1000, 0, 0), true, 0);
+        session.update(TCP_SYN | TCP_ACK, &segment(5000, 1001, 0), false, 20_000);
+        session.update(TCP_ACK, &segment(1001, 5001, 0), true, 45_000);
+    }
+
+    #[test]
+    fn sequence_comparison_wraps_around() {
+        assert!(sequence_after(5, u32::MAX - 5));
+        assert!(!sequence_after(u32::MAX - 5, 5));
+        assert!(!sequence_after(7, 7));
+    }
+
+    #[test]
+    fn handshake_rtt_spans_syn_to_final_ack() {
+        let mut session = TcpSession::default();
+        handshake(&mut session);
+        let stats = session.to_stats();
+        assert_eq!(stats.handshake_rtt_us, Some(45_000));
+        assert_eq!(stats.segments, 2);
+        assert_eq!(stats.retransmissions, 0);
+    }
+
+    #[test]
+    fn handshake_requires_matching_acknowledgments() {
+        let mut session = TcpSession::default();
+        session.update(TCP_SYN, &segment(1000, 0, 0), true, 0);
+        session.update(TCP_SYN | TCP_ACK, &segment(5000, 42, 0), false, 20_000);
+        session.update(TCP_ACK, &segment(1001, 5001, 0), true, 45_000);
+        assert_eq!(session.to_stats().handshake_rtt_us, None);
+    }
+
+    #[test]
+    fn data_across_sequence_wrap_is_not_retransmitted() {
+        let mut session = TcpSession::default();
+        let start = u32::MAX - 99;
+        session.update(PSH_ACK, &segment(start, 1, 100), true, 0);
+        session.update(PSH_ACK, &segment(start.wrapping_add(100), 1, 100), true, 10_000);
+        session.update(PSH_ACK, &segment(100, 1, 100), true, 20_000);
+        let stats = session.to_stats();
+        assert_eq!(stats.segments, 3);
+        assert_eq!(stats.retransmissions, 0);
+        assert_eq!(stats.out_of_order, 0);
+    }
+
+    #[test]
+    fn late_covered_segment_is_a_retransmission() {
+        let mut session = TcpSession::default();
+        session.update(PSH_ACK, &segment(1, 1, 100), true, 0);
+        session.update(PSH_ACK, &segment(1, 1, 100), true, 200_000);
+        let stats = session.to_stats();
+        assert_eq!(stats.retransmissions, 1);
+        assert_eq!(stats.out_of_order, 0);
+    }
+
+    #[test]
+    fn covered_segment_right_after_advance_is_out_of_order() {
+        let mut session = TcpSession::default();
+        // Il secondo segmento arriva prima del primo.
+        session.update(PSH_ACK, &segment(101, 1, 100), true, 0);
+        session.update(PSH_ACK, &segment(1, 1, 100), true, 1_000);
+        let stats = session.to_stats();
+        assert_eq!(stats.out_of_order, 1);
+        assert_eq!(stats.retransmissions, 0);
+    }
+
+    #[test]
+    fn zero_window_and_reset_are_reported() {
+        let mut session = TcpSession::default();
+        handshake(&mut session);
+        session.update(TCP_ACK, &TcpInfo { sequence: 5001, acknowledgment: 1001, window: 0, payload_len: 0 }, false, 50_000);
+        session.update(TCP_FIN | TCP_ACK, &segment(1001, 5001, 0), true, 60_000);
+        session.update(TCP_RST, &TcpInfo { sequence: 5001, acknowledgment: 0, window: 0, payload_len: 0 }, false, 70_000);
+        let stats = session.to_stats();
+        assert_eq!(stats.zero_window_events, 1);
+        assert_eq!(stats.closed_by.as_deref(), Some("rst"));
+    }
+
+    #[test]
+    fn zero_window_counts_transitions_per_direction() {
+        let window = |window: u16| TcpInfo { sequence: 1, acknowledgment: 1, window, payload_len: 0 };
+        let mut session = TcpSession::default();
+        // Stallo del client con tre window probe, poi riapertura e nuovo stallo.
+        for _ in 0..4 {
+            session.update(TCP_ACK, &window(0), true, 0);
+        }
+        session.update(TCP_ACK, &window(1024), true, 0);
+        session.update(TCP_ACK, &window(0), true, 0);
+        // Il server entra in finestra nulla in modo indipendente.
+        session.update(TCP_ACK, &window(0), false, 0);
+        session.update(TCP_ACK, &window(0), false, 0);
+        // FIN con finestra nulla dopo la riapertura: non è uno stallo.
+        session.update(TCP_ACK, &window(512), false, 0);
+        session.update(TCP_FIN | TCP_ACK, &window(0), false, 0);
+        assert_eq!(session.to_stats().zero_window_events, 3);
+    }
+
+    #[test]
+    fn merged_health_averages_over_all_handshakes() {
+        let mut first = TcpSession::default();
+        handshake(&mut first);
+        let mut second = TcpSession::default();
+        second.update(TCP_SYN, &segment(1, 0, 0), true, 0);
+        second.update(TCP_SYN | TCP_ACK, &segment(9, 2, 0), false, 5_000);
+        second.update(TCP_ACK, &segment(2, 10, 0), true, 15_000);
+
+        let mut left = TcpHealth::default();
+        left.add_session(&first);
+        let mut right = TcpHealth::default();
+        right.add_session(&second);
+        right.add_session(&TcpSession::default());
+        left.merge(&right);
+
+        let stats = left.to_stats();
+        assert_eq!(stats.flows, 3);
+        assert_eq!(stats.handshakes_completed, 2);
+        assert_eq!(stats.avg_handshake_rtt_us, 30_000.0);
+        assert_eq!(stats.max_handshake_rtt_us, Some(45_000));
+    }
+}
@@ -3,12 +3,15 @@ use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use notify::{RecommendedWatcher, RecursiveMode, Result as NotifyResult, Watcher};
-use crate::model::PacketData;
+use crate::model::StatsConfig;
+use crate::geoip_helper::GeoIpDatabase;
+use crate::dns_helper::SharedPassiveDns;
+use crate::stat_helper::StatsAccumulator;
 use crate::thread::model::{Thread, ThreadWatcher, ThreadWithState, ThreadWorker};
 
 pub enum ThreadHandle {
     Watcher(ThreadWatcher),
-    Aggregator(ThreadWithState<Vec<PacketData>>),
+    Aggregator(ThreadWithState<StatsAccumulator>),
     Worker(ThreadWorker),
 }
 
@@ -20,17 +23,20 @@ pub enum ThreadType {
     },
     Aggregator {
         name: String,
-        packet_rx: Receiver<Vec<PacketData>>,
+        summary_rx: Receiver<StatsAccumulator>,
+        settings: StatsConfig,
+        geoip: Option<Arc<GeoIpDatabase>>,
+        passive_dns: SharedPassiveDns,
         save_stats: Box<
-            dyn Fn(&Vec<PacketData>) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+            dyn Fn(&StatsAccumulator) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
         >,
     },
     Worker {
         name: String,
         job_rx: Arc<Mutex<Receiver<(String, String)>>>,
-        packet_tx: Arc<Sender<Vec<PacketData>>>,
+        summary_tx: Arc<Sender<StatsAccumulator>>,
         worker_fn:
-            Arc<dyn Fn(String, String) -> Result<Vec<PacketData>, Box<dyn Error>> + Send + Sync + 'static>,
+            Arc<dyn Fn(String, String) -> Result<StatsAccumulator, Box<dyn Error>> + Send + Sync + 'static>,
     },
 }
 
@@ -63,7 +69,7 @@ fn create_watcher(
                     || matches!(event.kind, notify::EventKind::Modify(_))
                 {
                     for path in event.paths {
-                        if path.extension().and_then(|e| e.to_str()) == Some("pcap") {
+                        if crate::util::is_capture_file(&path) {
                             println!("[{}] Nuovo file pcap: {:?}", name_cloned, path);
                             if let Err(e) = sender.send(path.clone()) {
                                 eprintln!("[{}] Errore invio: {}", name_cloned, e);
@@ -85,19 +91,24 @@ fn create_watcher(
     })
 }
 
+/// L'aggregatore unisce i riepiloghi dei singoli file man mano che arrivano:
+/// il costo di ogni aggiornamento dipende dalle chiavi distinte, non dai pacchetti.
 pub fn create_stats_aggregator(
     name: &str,
-    packet_rx: Receiver<Vec<PacketData>>,
-    save_stats: Box<dyn Fn(&Vec<PacketData>) -> Result<(), Box<dyn Error>> + Send + Sync + 'static>,
-) -> ThreadWithState<Vec<PacketData>> {
+    summary_rx: Receiver<StatsAccumulator>,
+    settings: &StatsConfig,
+    geoip: Option<Arc<GeoIpDatabase>>,
+    passive_dns: SharedPassiveDns,
+    save_stats: Box<dyn Fn(&StatsAccumulator) -> Result<(), Box<dyn Error>> + Send + Sync + 'static>,
+) -> ThreadWithState<StatsAccumulator> {
     let name = name.to_string();
 
     ThreadWithState::new(
         &name,
-        Vec::new(),
-        packet_rx,
-        move |state: &mut Vec<PacketData>, batch: Vec<PacketData>| {
-            state.extend(batch);
+        StatsAccumulator::new(settings, geoip, passive_dns),
+        summary_rx,
+        move |state: &mut StatsAccumulator, summary: StatsAccumulator| {
+            state.merge(summary);
             if let Err(e) = save_stats(state) {
                 eprintln!("Errore salvataggio stats: {}", e);
             }
@@ -119,22 +130,25 @@ pub fn create_thread(thread_type: ThreadType) -> ThreadHandle {
         },
         ThreadType::Aggregator {
             name,
-            packet_rx,
+            summary_rx,
+            settings,
+            geoip,
+            passive_dns,
             save_stats,
         } => {
-            let aggregator = create_stats_aggregator(&name, packet_rx, save_stats);
+            let aggregator = create_stats_aggregator(&name, summary_rx, &settings, geoip, passive_dns, save_stats);
             ThreadHandle::Aggregator(aggregator)
         }
         ThreadType::Worker {
             name,
             job_rx,
-            packet_tx,
+            summary_tx,
             worker_fn,
         } => {
-            let worker = ThreadWorker::new::<Vec<PacketData>, _>(
+            let worker = ThreadWorker::new::<StatsAccumulator, _>(
                 &name,
                 job_rx,
-                packet_tx,
+                summary_tx,
                 worker_fn,
             );
             ThreadHandle::Worker(worker)
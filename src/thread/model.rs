@@ -53,21 +53,21 @@ where
     pub fn new<Item, F>(
         name: &str,
         initial_state: T,
-        receiver: Receiver<Vec<Item>>,
+        receiver: Receiver<Item>,
         update_fn: F,
     ) -> Self
     where
         Item: Send + 'static,
-        F: Fn(&mut T, Vec<Item>) + Send + Sync + 'static,
+        F: Fn(&mut T, Item) + Send + Sync + 'static,
     {
         let state = Arc::new(Mutex::new(initial_state));
         let thread_state = Arc::clone(&state);
         let thread_name = name.to_string();
 
         let thread = Thread::new(name, move || {
-            while let Ok(batch) = receiver.recv() {
+            while let Ok(item) = receiver.recv() {
                 let mut locked = thread_state.lock().unwrap();
-                update_fn(&mut *locked, batch);
+                update_fn(&mut *locked, item);
             }
             println!("[{}] Thread Terminato", thread_name);
         });
@@ -0,0 +1,223 @@
+use crate::model::TlsInfo;
+use crate::util::ByteReader;
+
+const RECORD_HEADER_LEN: usize = 5;
+const RECORD_CHANGE_CIPHER_SPEC: u8 = 20;
+const RECORD_APPLICATION_DATA: u8 = 23;
+const RECORD_HANDSHAKE: u8 = 22;
+const RECORD_MAX_LEN: u16 = 16384 + 2048;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const EXTENSION_ALPN: u16 = 16;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 43;
+const SERVER_NAME_HOST: u8 = 0;
+
+/// Riconosce un record TLS dall'header: content type, versione 3.x e
+/// lunghezza entro il limite ammesso dalla specifica.
+pub fn is_tls_record(payload: &[u8]) -> bool {
+    match payload.get(..RECORD_HEADER_LEN) {
+        Some(header) => {
+            let length = u16::from_be_bytes([header[3], header[4]]);
+            (RECORD_CHANGE_CIPHER_SPEC..=RECORD_APPLICATION_DATA).contains(&header[0])
+                && header[1] == 3
+                && header[2] <= 4
+                && length <= RECORD_MAX_LEN
+        }
+        None => false,
+    }
+}
+
+/// Estrae SNI, ALPN e versione offerta da un ClientHello contenuto nel
+/// primo record del payload. Restituisce `None` per qualsiasi altro record.
+pub fn parse_client_hello(payload: &[u8]) -> Option<TlsInfo> {
+    if *payload.first()? != RECORD_HANDSHAKE {
+        return None;
+    }
+    let handshake = payload.get(RECORD_HEADER_LEN..)?;
+    if *handshake.first()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+
+    let mut reader = ByteReader::new(handshake.get(4..)?);
+    let client_version = reader.read_u16()?;
+    reader.skip(32)?;
+    let session_id_len = reader.read_u8()? as usize;
+    reader.skip(session_id_len)?;
+    let cipher_suites_len = reader.read_u16()? as usize;
+    reader.skip(cipher_suites_len)?;
+    let compression_len = reader.read_u8()? as usize;
+    reader.skip(compression_len)?;
+
+    let mut info = TlsInfo {
+        server_name: None,
+        alpn: Vec::new(),
+        version: version_name(client_version),
+    };
+
+    // Un ClientHello senza estensioni (SSL 3.0) è comunque valido.
+    let extensions_len = match reader.read_u16() {
+        Some(len) => len as usize,
+        None => return Some(info),
+    };
+    // Un ClientHello grande (key share post-quantum) prosegue oltre il segmento
+    // o lo snaplen: si analizzano le estensioni presenti fino al taglio.
+    let mut extensions = ByteReader::new(reader.take(extensions_len.min(reader.remaining()))?);
+
+    while let (Some(extension_type), Some(extension_len)) = (extensions.read_u16(), extensions.read_u16()) {
+        let data = match extensions.take(extension_len as usize) {
+            Some(data) => data,
+            None => break,
+        };
+        match extension_type {
+            EXTENSION_SERVER_NAME => info.server_name = parse_server_name(data),
+            EXTENSION_ALPN => info.alpn = parse_alpn(data),
+            EXTENSION_SUPPORTED_VERSIONS => {
+                if let Some(version) = highest_supported_version(data) {
+                    info.version = version_name(version);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(info)
+}
+
+fn parse_server_name(data: &[u8]) -> Option<String> {
+    let mut reader = ByteReader::new(data);
+    let list_len = reader.read_u16()? as usize;
+    let mut list = ByteReader::new(reader.take(list_len)?);
+
+    while let Some(name_type) = list.read_u8() {
+        let name_len = list.read_u16()? as usize;
+        let name = list.take(name_len)?;
+        if name_type == SERVER_NAME_HOST {
+            return Some(String::from_utf8_lossy(name).to_ascii_lowercase());
+        }
+    }
+    None
+}
+
+fn parse_alpn(data: &[u8]) -> Vec<String> {
+    let mut protocols = Vec::new();
+    let mut reader = ByteReader::new(data);
+    let list_len = reader.read_u16().unwrap_or(0) as usize;
+    let mut list = ByteReader::new(reader.take(list_len).unwrap_or(&[]));
+
+    while let Some(protocol_len) = list.read_u8() {
+        match list.take(protocol_len as usize) {
+            Some(protocol) => protocols.push(String::from_utf8_lossy(protocol).to_string()),
+            None => break,
+        }
+    }
+    protocols
+}
+
+fn highest_supported_version(data: &[u8]) -> Option<u16> {
+    let mut reader = ByteReader::new(data);
+    let list_len = reader.read_u8()? as usize;
+    let mut list = ByteReader::new(reader.take(list_len)?);
+    let mut highest = None;
+
+    while let Some(version) = list.read_u16() {
+        if !is_grease(version) && highest.is_none_or(|h| version > h) {
+            highest = Some(version);
+        }
+    }
+    highest
+}
+
+/// I valori GREASE (RFC 8701) hanno la forma 0x?A?A e vanno ignorati.
+fn is_grease(value: u16) -> bool {
+    value & 0x0F0F == 0x0A0A && (value >> 8) == (value & 0xFF)
+}
+
+fn version_name(version: u16) -> Option<String> {
+    let name = match version {
+        0x0300 => "SSL 3.0",
+        0x0301 => "TLS 1.0",
+        0x0302 => "TLS 1.1",
+        0x0303 => "TLS 1.2",
+        0x0304 => "TLS 1.3",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extension(extension_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = extension_type.to_be_bytes().to_vec();
+        bytes.extend((data.len() as u16).to_be_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn client_hello(extensions: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend([0u8; 32]);
+        body.push(0);
+        body.extend([0x00, 0x02, 0x13, 0x01]);
+        body.extend([0x01, 0x00]);
+        body.extend((extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+        handshake.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(body);
+
+        let mut record = vec![RECORD_HANDSHAKE, 0x03, 0x01];
+        record.extend((handshake.len() as u16).to_be_bytes());
+        record.extend(handshake);
+        record
+    }
+
+    fn sample_extensions() -> Vec<u8> {
+        let mut extensions = extension(EXTENSION_SERVER_NAME, &[0x00, 0x0E, 0x00, 0x00, 0x0B,
+            b'E', b'x', b'a', b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm']);
+        extensions.extend(extension(EXTENSION_ALPN, &[0x00, 0x0C, 0x02, b'h', b'2',
+            0x08, b'h', b't', b't', b'p', b'/', b'1', b'.', b'1']));
+        extensions.extend(extension(EXTENSION_SUPPORTED_VERSIONS, &[0x06, 0x3A, 0x3A, 0x03, 0x04, 0x03, 0x03]));
+        extensions
+    }
+
+    #[test]
+    fn parses_sni_alpn_and_supported_versions() {
+        let payload = client_hello(&sample_extensions());
+        assert!(is_tls_record(&payload));
+
+        let info = parse_client_hello(&payload).unwrap();
+        assert_eq!(info.server_name.as_deref(), Some("example.com"));
+        assert_eq!(info.alpn, vec!["h2", "http/1.1"]);
+        assert_eq!(info.version.as_deref(), Some("TLS 1.3"));
+    }
+
+    #[test]
+    fn keeps_extensions_read_before_truncation() {
+        let mut extensions = sample_extensions();
+        extensions.extend(extension(0x11EC, &[0u8; 1200]));
+        let payload = client_hello(&extensions);
+        let truncated = &payload[..payload.len() - 1000];
+
+        let info = parse_client_hello(truncated).unwrap();
+        assert_eq!(info.server_name.as_deref(), Some("example.com"));
+        assert_eq!(info.alpn, vec!["h2", "http/1.1"]);
+        assert_eq!(info.version.as_deref(), Some("TLS 1.3"));
+    }
+
+    #[test]
+    fn ignores_other_records() {
+        let mut payload = client_hello(&sample_extensions());
+        payload[0] = RECORD_APPLICATION_DATA;
+        assert!(parse_client_hello(&payload).is_none());
+        assert!(!is_tls_record(b"GET / HTTP/1.1"));
+    }
+
+    #[test]
+    fn grease_values_are_recognized() {
+        assert!(is_grease(0x3A3A));
+        assert!(!is_grease(0x0304));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::model::{DecapsulationConfig, Encapsulation};
+
+const PROTOCOL_IPV4_IN_IP: u8 = 4;
+const PROTOCOL_IPV6_IN_IP: u8 = 41;
+const PROTOCOL_GRE: u8 = 47;
+const PROTOCOL_UDP: u8 = 0x11;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_TEB: u16 = 0x6558;
+const ETHERTYPE_ERSPAN_II: u16 = 0x88BE;
+const ETHERTYPE_ERSPAN_III: u16 = 0x22EB;
+const GRE_FLAG_CHECKSUM: u16 = 0x8000;
+const GRE_FLAG_KEY: u16 = 0x2000;
+const GRE_FLAG_SEQUENCE: u16 = 0x1000;
+const GRE_VERSION_MASK: u16 = 0x0007;
+const UDP_HEADER_LEN: usize = 8;
+const VXLAN_HEADER_LEN: usize = 8;
+const VXLAN_FLAG_VNI: u8 = 0x08;
+const GENEVE_HEADER_LEN: usize = 8;
+const ERSPAN_II_HEADER_LEN: usize = 8;
+const ERSPAN_III_HEADER_LEN: usize = 12;
+const ERSPAN_III_SUBHEADER_LEN: usize = 8;
+
+/// Contenuto trasportato da un tunnel: un frame Ethernet completo oppure
+/// direttamente un pacchetto IP con il relativo ethertype.
+pub enum InnerFrame<'a> {
+    Ethernet(&'a [u8]),
+    Ip(u16, &'a [u8]),
+}
+
+/// Riconosce un incapsulamento a partire dal protocollo IP e dal payload che
+/// segue l'header IP, restituendo il tipo di tunnel e il frame interno.
+pub fn tunnel_payload<'a>(
+    protocol: u8,
+    payload: &'a [u8],
+    config: &DecapsulationConfig,
+) -> Option<(Encapsulation, InnerFrame<'a>)> {
+    match protocol {
+        PROTOCOL_IPV4_IN_IP => Some((Encapsulation::IpInIp, InnerFrame::Ip(ETHERTYPE_IPV4, payload))),
+        PROTOCOL_IPV6_IN_IP => Some((Encapsulation::IpInIp, InnerFrame::Ip(ETHERTYPE_IPV6, payload))),
+        PROTOCOL_GRE => gre_payload(payload),
+        PROTOCOL_UDP => udp_tunnel_payload(payload, config),
+        _ => None,
+    }
+}
+
+fn gre_payload(payload: &[u8]) -> Option<(Encapsulation, InnerFrame<'_>)> {
+    let header = payload.get(..4)?;
+    let flags = u16::from_be_bytes([header[0], header[1]]);
+    let protocol_type = u16::from_be_bytes([header[2], header[3]]);
+
+    // La versione 1 è l'Enhanced GRE di PPTP, che trasporta PPP e non IP.
+    if flags & GRE_VERSION_MASK != 0 {
+        return None;
+    }
+
+    let mut offset = 4;
+    if flags & GRE_FLAG_CHECKSUM != 0 {
+        offset += 4;
+    }
+    if flags & GRE_FLAG_KEY != 0 {
+        offset += 4;
+    }
+    if flags & GRE_FLAG_SEQUENCE != 0 {
+        offset += 4;
+    }
+    let inner = payload.get(offset..)?;
+
+    match protocol_type {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => Some((Encapsulation::Gre, InnerFrame::Ip(protocol_type, inner))),
+        ETHERTYPE_TEB => Some((Encapsulation::Gre, InnerFrame::Ethernet(inner))),
+        ETHERTYPE_ERSPAN_II => {
+            // ERSPAN tipo I non ha header proprio e si distingue per l'assenza del sequence number.
+            let frame = if flags & GRE_FLAG_SEQUENCE != 0 {
+                inner.get(ERSPAN_II_HEADER_LEN..)?
+            } else {
+                inner
+            };
+            Some((Encapsulation::Erspan, InnerFrame::Ethernet(frame)))
+        }
+        ETHERTYPE_ERSPAN_III => {
+            let header = inner.get(..ERSPAN_III_HEADER_LEN)?;
+            let has_subheader = header[ERSPAN_III_HEADER_LEN - 1] & 0x01 != 0;
+            let header_len = if has_subheader {
+                ERSPAN_III_HEADER_LEN + ERSPAN_III_SUBHEADER_LEN
+            } else {
+                ERSPAN_III_HEADER_LEN
+            };
+            Some((Encapsulation::Erspan, InnerFrame::Ethernet(inner.get(header_len..)?)))
+        }
+        _ => None,
+    }
+}
+
+fn udp_tunnel_payload<'a>(
+    payload: &'a [u8],
+    config: &DecapsulationConfig,
+) -> Option<(Encapsulation, InnerFrame<'a>)> {
+    let header = payload.get(..UDP_HEADER_LEN)?;
+    let destination_port = u16::from_be_bytes([header[2], header[3]]);
+    let udp_payload = &payload[UDP_HEADER_LEN..];
+
+    if config.vxlan_ports.contains(&destination_port) {
+        let vxlan = udp_payload.get(..VXLAN_HEADER_LEN)?;
+        if vxlan[0] & VXLAN_FLAG_VNI == 0 {
+            return None;
+        }
+        return Some((Encapsulation::Vxlan, InnerFrame::Ethernet(&udp_payload[VXLAN_HEADER_LEN..])));
+    }
+
+    if config.geneve_ports.contains(&destination_port) {
+        let geneve = udp_payload.get(..GENEVE_HEADER_LEN)?;
+        let options_len = (geneve[0] & 0x3F) as usize * 4;
+        let protocol_type = u16::from_be_bytes([geneve[2], geneve[3]]);
+        let inner = udp_payload.get(GENEVE_HEADER_LEN + options_len..)?;
+        return match protocol_type {
+            ETHERTYPE_TEB => Some((Encapsulation::Geneve, InnerFrame::Ethernet(inner))),
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => Some((Encapsulation::Geneve, InnerFrame::Ip(protocol_type, inner))),
+            _ => None,
+        };
+    }
+
+    None
+}
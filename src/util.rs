@@ -22,6 +22,27 @@ pub fn write_json_file<P, T>(file_path: P, data: &T) -> Result<(), Box<dyn std::
     return Ok(());
 }
 
+/// Estensioni dei file di cattura accettati da watcher e dispatcher,
+/// eventualmente seguite da un'estensione di compressione.
+const CAPTURE_EXTENSIONS: [&str; 2] = ["pcap", "pcapng"];
+const COMPRESSION_EXTENSIONS: [&str; 3] = ["gz", "zst", "xz"];
+
+/// Nome logico della cattura, senza l'estensione di compressione:
+/// "traffico.pcap.gz" diventa "traffico.pcap".
+pub fn capture_name(path: &Path) -> Option<&str> {
+    let file_name = path.file_name()?.to_str()?;
+    match file_name.rsplit_once('.') {
+        Some((base, extension)) if COMPRESSION_EXTENSIONS.contains(&extension) => Some(base),
+        _ => Some(file_name),
+    }
+}
+
+pub fn is_capture_file(path: &Path) -> bool {
+    capture_name(path)
+        .and_then(|name| name.rsplit_once('.'))
+        .is_some_and(|(_, extension)| CAPTURE_EXTENSIONS.contains(&extension))
+}
+
 pub fn update_file<P, T>(file_path: P, data: &T) -> Result<(), Box<dyn std::error::Error>>
 where
     P: AsRef<Path>,
@@ -53,4 +74,40 @@ where
             Err(e)
         },
     }
-}
\ No newline at end of file
+}
+
+/// Lettore sequenziale big-endian per i payload dei protocolli applicativi:
+/// ogni lettura oltre la fine del buffer restituisce `None`.
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        ByteReader { data, offset: 0 }
+    }
+
+    pub fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    /// Byte ancora da leggere.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    pub fn read_u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub fn read_u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}